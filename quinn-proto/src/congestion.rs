@@ -1,17 +1,26 @@
 //! Logic for controlling the rate at which data is sent
 
-use crate::Instant;
 use crate::connection::RttEstimator;
+use crate::{Duration, Instant};
 use std::any::Any;
 use std::sync::Arc;
 
+mod adaptive;
 mod bbr;
 mod cubic;
 mod new_reno;
-
-pub use bbr::{Bbr, BbrConfig};
+mod pacing;
+#[cfg(test)]
+mod tests;
+
+pub use adaptive::AdaptiveControllerConfig;
+pub use bbr::{
+    Bandwidth, Bbr, BbrConfig, BbrMode, BbrParameters, BbrState, Gains, ModeDurations, PacingMode,
+    ProbeBwPhase, RateSample,
+};
 pub use cubic::{Cubic, CubicConfig};
 pub use new_reno::{NewReno, NewRenoConfig};
+pub use pacing::PacingController;
 
 /// Common interface for different congestion controllers
 pub trait Controller: Send + Sync {
@@ -34,6 +43,25 @@ pub trait Controller: Send + Sync {
     ) {
     }
 
+    /// `count` packets of `bytes` bytes each were acknowledged at once
+    ///
+    /// `sent` is the time at which the most recent of these packets was sent. Equivalent to
+    /// calling [`on_ack`](Self::on_ack) once per packet, which is what the default implementation
+    /// does; controllers may override it to process the whole range at once.
+    fn on_ack_range(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        count: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        for _ in 0..count {
+            self.on_ack(now, sent, bytes, app_limited, rtt);
+        }
+    }
+
     /// Packets are acked in batches, all with the same `now` argument. This indicates one of those batches has completed.
     #[allow(unused_variables)]
     fn on_end_acks(
@@ -59,21 +87,68 @@ pub trait Controller: Send + Sync {
         lost_bytes: u64,
     );
 
+    /// The connection resumes sending after having been idle for `idle_duration`
+    ///
+    /// Called when a packet is sent while nothing was in flight, and more than a smoothed RTT has
+    /// passed since the previous packet counting towards the bytes in flight was sent.
+    #[allow(unused_variables)]
+    fn on_idle(&mut self, idle_duration: Duration) {}
+
+    /// The peer's address changed while the congestion state was carried over, e.g. after a NAT
+    /// rebinding
+    fn on_path_change(&mut self) {}
+
     /// The known MTU for the current network path has been updated
     fn on_mtu_update(&mut self, new_mtu: u16);
 
     /// Number of ack-eliciting bytes that may be in flight
     fn window(&self) -> u64;
 
+    /// Whether the controller rather than the application limits sending with `in_flight` bytes
+    /// outstanding
+    fn is_cwnd_limited(&self, in_flight: u64) -> bool {
+        in_flight >= self.window()
+    }
+
+    /// Estimated bottleneck bandwidth of the path in bytes per second, if the controller tracks one
+    fn bandwidth_estimate(&self) -> Option<u64> {
+        None
+    }
+
+    /// Rate in bytes per second at which the controller would like packets to be paced, if any
+    ///
+    /// Unlike [`ControllerMetrics::pacing_rate`], this is not scaled to bits per second.
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    /// Earliest time at which `bytes_pending` more bytes may be sent, given that the previous
+    /// packet was sent at `last_send`
+    ///
+    /// Returns `None` if the controller doesn't pace its transmissions.
+    #[allow(unused_variables)]
+    fn next_send_time(
+        &self,
+        now: Instant,
+        last_send: Instant,
+        bytes_pending: u64,
+    ) -> Option<Instant> {
+        None
+    }
+
     /// Retrieve implementation-specific metrics used to populate `qlog` traces when they are enabled
     fn metrics(&self) -> ControllerMetrics {
         ControllerMetrics {
             congestion_window: self.window(),
-            ssthresh: None,
-            pacing_rate: None,
+            ..Default::default()
         }
     }
 
+    /// Short, human-readable name of the congestion control algorithm, e.g. for logging
+    fn name(&self) -> &'static str {
+        "unknown"
+    }
+
     /// Duplicate the controller's state
     fn clone_box(&self) -> Box<dyn Controller>;
 
@@ -93,7 +168,31 @@ pub struct ControllerMetrics {
     /// Slow start threshold (bytes)
     pub ssthresh: Option<u64>,
     /// Pacing rate (bits/s)
+    ///
+    /// Rates too large to be represented in bits per second saturate at `u64::MAX`.
     pub pacing_rate: Option<u64>,
+    /// Most recently measured delivery rate (bits/s)
+    ///
+    /// Saturates at `u64::MAX`, like `pacing_rate`.
+    pub delivery_rate: Option<u64>,
+    /// Whether the most recent delivery rate sample was taken while application-limited
+    ///
+    /// Such samples only provide a lower bound on the available bandwidth, so consumers will
+    /// usually want to filter them out.
+    pub delivery_rate_app_limited: bool,
+    /// Number of round trips counted by the controller
+    pub round_count: Option<u64>,
+    /// Number of times the controller advanced its bandwidth probing gain cycle
+    pub cycle_count: Option<u64>,
+    /// Fraction of the controller's lifetime spent in its steady state, e.g. BBR's PROBE_BW
+    pub steady_state_fraction: Option<f64>,
+    /// Phase of BBR's PROBE_BW gain cycle, while in that mode
+    pub probe_bw_phase: Option<ProbeBwPhase>,
+    /// Estimated queueing delay, i.e. how far the latest RTT exceeds the minimum RTT
+    pub queue_delay: Option<Duration>,
+    /// Estimated bandwidth-delay product (bytes), the amount of data the path holds when fully
+    /// utilized without queueing
+    pub bdp: Option<u64>,
 }
 
 /// Constructs controllers on demand
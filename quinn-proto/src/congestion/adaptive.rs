@@ -0,0 +1,59 @@
+use std::fmt;
+use std::sync::Arc;
+
+use super::{BbrConfig, Controller, ControllerFactory, CubicConfig};
+use crate::Instant;
+
+/// Builds a [`Bbr`](super::Bbr) controller when a runtime condition holds, and a fallback
+/// controller otherwise
+///
+/// The condition is evaluated every time a controller is built, so it may depend on state that
+/// changes at runtime, e.g. whether ECN has been found to work on the network.
+#[derive(Clone)]
+pub struct AdaptiveControllerConfig {
+    condition: Arc<dyn Fn() -> bool + Send + Sync>,
+    bbr: Arc<BbrConfig>,
+    fallback: Arc<dyn ControllerFactory + Send + Sync>,
+}
+
+impl AdaptiveControllerConfig {
+    /// Build BBR controllers whenever `condition` returns `true`, and Cubic controllers otherwise
+    pub fn new(condition: Arc<dyn Fn() -> bool + Send + Sync>) -> Self {
+        Self {
+            condition,
+            bbr: Arc::new(BbrConfig::default()),
+            fallback: Arc::new(CubicConfig::default()),
+        }
+    }
+
+    /// Configuration of the BBR controllers
+    pub fn bbr(&mut self, value: Arc<BbrConfig>) -> &mut Self {
+        self.bbr = value;
+        self
+    }
+
+    /// Factory for the controllers built when the condition doesn't hold
+    ///
+    /// Defaults to Cubic.
+    pub fn fallback(&mut self, value: Arc<dyn ControllerFactory + Send + Sync>) -> &mut Self {
+        self.fallback = value;
+        self
+    }
+}
+
+impl fmt::Debug for AdaptiveControllerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdaptiveControllerConfig")
+            .field("bbr", &self.bbr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ControllerFactory for AdaptiveControllerConfig {
+    fn build(self: Arc<Self>, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        match (self.condition)() {
+            true => self.bbr.clone().build(now, current_mtu),
+            false => self.fallback.clone().build(now, current_mtu),
+        }
+    }
+}
@@ -15,9 +15,18 @@ pub(crate) struct BandwidthEstimation {
     prev_sent_time: Option<Instant>,
     max_filter: MinMax,
     acked_at_last_window: u64,
+    last_sample: Option<DeliveryRateSample>,
 }
 
 impl BandwidthEstimation {
+    /// Construct an estimator remembering the maximum delivery rate of the last `window_rounds`
+    pub(crate) fn new(window_rounds: u64) -> Self {
+        Self {
+            max_filter: MinMax::new(window_rounds),
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn on_sent(&mut self, now: Instant, bytes: u64) {
         self.prev_total_sent = self.total_sent;
         self.total_sent += bytes;
@@ -25,23 +34,33 @@ impl BandwidthEstimation {
         self.sent_time = Some(now);
     }
 
+    /// Account for `bytes` acknowledged at `now`, returning the delivery rate sample taken
+    ///
+    /// No sample is taken if the acknowledgement arrives no later than the packet was sent, e.g.
+    /// on loopback or due to coarse clocks, or no later than the previous acknowledgement, as the
+    /// rate over such an interval is undefined. Neither is one taken for the first
+    /// acknowledgement, which has no interval to measure the rate over.
     pub(crate) fn on_ack(
         &mut self,
         now: Instant,
-        _sent: Instant,
+        sent: Instant,
         bytes: u64,
         round: u64,
         app_limited: bool,
-    ) {
+    ) -> Option<DeliveryRateSample> {
         self.prev_total_acked = self.total_acked;
         self.total_acked += bytes;
         self.prev_acked_time = self.acked_time;
         self.acked_time = Some(now);
 
-        let prev_sent_time = match self.prev_sent_time {
-            Some(prev_sent_time) => prev_sent_time,
-            None => return,
-        };
+        let prev_sent_time = self.prev_sent_time?;
+        let ack_interval = self
+            .prev_acked_time
+            .and_then(|prev_acked_time| now.checked_duration_since(prev_acked_time))
+            .filter(|interval| !interval.is_zero())?;
+        if now <= sent {
+            return None;
+        }
 
         let send_rate = match self.sent_time {
             Some(sent_time) if sent_time > prev_sent_time => Self::bw_from_delta(
@@ -52,19 +71,42 @@ impl BandwidthEstimation {
             _ => u64::MAX, // will take the min of send and ack, so this is just a skip
         };
 
-        let ack_rate = match self.prev_acked_time {
-            Some(prev_acked_time) => Self::bw_from_delta(
-                self.total_acked - self.prev_total_acked,
-                now - prev_acked_time,
-            )
-            .unwrap_or(0),
-            None => 0,
-        };
+        let ack_rate = Self::bw_from_delta(self.total_acked - self.prev_total_acked, ack_interval)
+            .unwrap_or(0);
 
         let bandwidth = send_rate.min(ack_rate);
-        if !app_limited && self.max_filter.get() < bandwidth {
+        let sample = DeliveryRateSample {
+            rate: bandwidth,
+            app_limited,
+        };
+        self.last_sample = Some(sample);
+        // Keep a windowed maximum over recent rounds so that the estimate follows sustained
+        // changes while riding out transient dips. App-limited samples underestimate the
+        // bandwidth, so they're only considered if they exceed the current estimate anyway.
+        if bandwidth != 0 && (!app_limited || bandwidth > self.max_filter.get()) {
             self.max_filter.update_max(round, bandwidth);
         }
+        Some(sample)
+    }
+
+    /// Account for `bytes` acknowledged at the same time as the previous acknowledgement
+    ///
+    /// Such acknowledgements span no time and so yield no delivery rate sample.
+    pub(crate) fn on_simultaneous_ack(&mut self, bytes: u64) {
+        self.prev_total_acked = self.total_acked;
+        self.total_acked += bytes;
+        self.prev_acked_time = self.acked_time;
+    }
+
+    /// Start out with a bandwidth estimate carried over from elsewhere
+    pub(crate) fn seed(&mut self, bandwidth: u64) {
+        self.reset_estimate(0, bandwidth);
+    }
+
+    /// Discard all samples, replacing them with `bandwidth` measured in `round`
+    pub(crate) fn reset_estimate(&mut self, round: u64, bandwidth: u64) {
+        self.max_filter.reset();
+        self.max_filter.update_max(round, bandwidth);
     }
 
     pub(crate) fn bytes_acked_this_window(&self) -> u64 {
@@ -75,27 +117,75 @@ impl BandwidthEstimation {
         self.acked_at_last_window = self.total_acked;
     }
 
-    pub(crate) fn get_estimate(&self) -> u64 {
-        self.max_filter.get()
+    #[cfg(test)]
+    pub(crate) fn max_filter(&self) -> &MinMax {
+        &self.max_filter
     }
 
+    pub(crate) fn get_estimate(&self) -> Bandwidth {
+        Bandwidth(self.max_filter.get())
+    }
+
+    /// The delivery rate measured by the most recent acknowledgement, if any
+    pub(crate) fn last_sample(&self) -> Option<DeliveryRateSample> {
+        self.last_sample
+    }
+
+    /// Rate at which `bytes` were transferred over `delta`, saturating at `u64::MAX`
     pub(crate) const fn bw_from_delta(bytes: u64, delta: Duration) -> Option<u64> {
         let window_duration_ns = delta.as_nanos();
         if window_duration_ns == 0 {
             return None;
         }
-        let b_ns = bytes * 1_000_000_000;
-        let bytes_per_second = b_ns / (window_duration_ns as u64);
-        Some(bytes_per_second)
+        // Widened, as a few gigabytes in nanoseconds overflow a u64
+        let b_ns = bytes as u128 * 1_000_000_000;
+        let bytes_per_second = b_ns / window_duration_ns;
+        if bytes_per_second > u64::MAX as u128 {
+            return Some(u64::MAX);
+        }
+        Some(bytes_per_second as u64)
     }
 }
 
+/// A data rate, stored in bytes per second
+///
+/// Rates are reported in bits per second in [`ControllerMetrics`](crate::congestion::ControllerMetrics)
+/// but tracked in bytes per second by the controllers; this type makes the unit explicit.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Bandwidth(u64);
+
+impl Bandwidth {
+    /// Construct from a rate in bytes per second
+    pub const fn from_bytes_per_sec(value: u64) -> Self {
+        Self(value)
+    }
+
+    /// The rate in bytes per second
+    pub const fn as_bytes_per_sec(self) -> u64 {
+        self.0
+    }
+
+    /// The rate in bits per second, saturating at `u64::MAX`
+    pub const fn as_bits_per_sec(self) -> u64 {
+        self.0.saturating_mul(8)
+    }
+}
+
+/// A single delivery rate measurement taken while processing an acknowledgement
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct DeliveryRateSample {
+    /// Delivery rate in bytes per second
+    pub(crate) rate: u64,
+    /// Whether the sender was application-limited when the acknowledged data was sent
+    pub(crate) app_limited: bool,
+}
+
 impl Display for BandwidthEstimation {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "{:.3} MB/s",
-            self.get_estimate() as f32 / (1024 * 1024) as f32
+            self.get_estimate().as_bytes_per_sec() as f32 / (1024 * 1024) as f32
         )
     }
 }
@@ -28,14 +28,23 @@
 use std::fmt::Debug;
 
 #[derive(Copy, Clone, Debug)]
-pub(super) struct MinMax {
+pub(crate) struct MinMax {
     /// round count, not a timestamp
     window: u64,
     samples: [MinMaxSample; 3],
 }
 
 impl MinMax {
-    pub(super) fn get(&self) -> u64 {
+    /// Construct a filter remembering samples from the last `window` rounds
+    pub(crate) fn new(window: u64) -> Self {
+        Self {
+            window,
+            samples: [Default::default(); 3],
+        }
+    }
+
+    /// The maximum over the window, or 0 if there are no samples
+    pub(crate) fn get(&self) -> u64 {
         self.samples[0].value
     }
 
@@ -43,12 +52,12 @@ impl MinMax {
         self.samples.fill(sample);
     }
 
-    pub(super) fn reset(&mut self) {
+    pub(crate) fn reset(&mut self) {
         self.fill(Default::default())
     }
 
     /// update_min is also defined in the original source, but removed here since it is not used.
-    pub(super) fn update_max(&mut self, current_round: u64, measurement: u64) {
+    pub(crate) fn update_max(&mut self, current_round: u64, measurement: u64) {
         let sample = MinMaxSample {
             time: current_round,
             value: measurement,
@@ -110,10 +119,7 @@ impl MinMax {
 
 impl Default for MinMax {
     fn default() -> Self {
-        Self {
-            window: 10,
-            samples: [Default::default(); 3],
-        }
+        Self::new(10)
     }
 }
 
@@ -149,4 +155,20 @@ mod test {
         min_max.update_max(round + 18, 130);
         assert_eq!(130, min_max.get());
     }
+
+    #[test]
+    fn max_decays_after_window() {
+        let mut min_max = MinMax::new(4);
+        min_max.update_max(0, 500);
+        min_max.update_max(3, 200);
+        assert_eq!(500, min_max.get());
+        // The maximum falls out of the window, leaving the best of the remaining samples
+        min_max.update_max(5, 100);
+        assert_eq!(200, min_max.get());
+        min_max.update_max(8, 100);
+        assert_eq!(100, min_max.get());
+
+        min_max.reset();
+        assert_eq!(0, min_max.get());
+    }
 }
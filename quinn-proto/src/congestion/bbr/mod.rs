@@ -1,19 +1,26 @@
 use std::any::Any;
-use std::fmt::Debug;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::mem;
 use std::sync::Arc;
 
 use rand::{Rng, SeedableRng};
 
-use crate::congestion::ControllerMetrics;
 use crate::congestion::bbr::bw_estimation::BandwidthEstimation;
 use crate::congestion::bbr::min_max::MinMax;
+use crate::congestion::{ControllerMetrics, NewReno, NewRenoConfig};
 use crate::connection::RttEstimator;
-use crate::{Duration, Instant};
+use crate::{ConfigError, Duration, Instant};
 
 use super::{BASE_DATAGRAM_SIZE, Controller, ControllerFactory};
 
 mod bw_estimation;
 mod min_max;
+#[cfg(test)]
+mod tests;
+
+pub use bw_estimation::Bandwidth;
+use bw_estimation::DeliveryRateSample;
 
 /// Experimental! Use at your own risk.
 ///
@@ -27,107 +34,552 @@ pub struct Bbr {
     current_mtu: u64,
     max_bandwidth: BandwidthEstimation,
     acked_bytes: u64,
-    mode: Mode,
+    sent_bytes: u64,
+    lost_bytes: u64,
+    mode: BbrMode,
     loss_state: LossState,
     recovery_state: RecoveryState,
     recovery_window: u64,
+    /// Loss-free rounds since the last loss in recovery
+    recovery_clean_rounds: u64,
     is_at_full_bandwidth: bool,
     pacing_gain: f32,
     high_gain: f32,
-    drain_gain: f32,
     cwnd_gain: f32,
     high_cwnd_gain: f32,
-    last_cycle_start: Option<Instant>,
-    current_cycle_offset: u8,
+    startup_started_at: Option<Instant>,
+    drain_round_count: u64,
+    /// Index of the current phase in the configured gain cycle
+    current_cycle_offset: usize,
+    cycle_start_round: u64,
+    cycle_count: u64,
     init_cwnd: u64,
     min_cwnd: u64,
     prev_in_flight_count: u64,
     exit_probe_rtt_at: Option<Instant>,
     probe_rtt_last_started_at: Option<Instant>,
     min_rtt: Duration,
+    min_rtt_valid: bool,
+    latest_rtt: Duration,
+    smoothed_rtt: Duration,
     exiting_quiescence: bool,
     pacing_rate: u64,
     max_acked_packet_number: u64,
     max_sent_packet_number: u64,
     end_recovery_at_packet_number: u64,
     cwnd: u64,
-    current_round_trip_end_packet_number: u64,
+    current_round_trip_start: Option<Instant>,
+    is_round_trip_end_acked: bool,
     round_count: u64,
+    round_start_cwnd: u64,
     bw_at_last_round: u64,
     round_wo_bw_gain: u64,
+    lossy_round_count: u64,
+    stalled_round_count: u64,
+    round_max_delivery_rate: u64,
+    /// Rounds with a full, i.e. not application-limited, delivery rate sample since the bandwidth
+    /// estimate was last reset, up to the length of the bandwidth filter window
+    full_sample_rounds: u64,
+    bandwidth_drop: BandwidthDropState,
     ack_aggregation: AckAggregationState,
+    burst: BurstState,
+    mode_durations: ModeDurations,
+    mode_durations_updated_at: Option<Instant>,
+    /// Loss-based window tracked alongside the model with `hybrid_cwnd`, created on first send
+    aimd: Option<NewReno>,
+    /// Delivery rate samples not yet collected by [`take_samples`](Self::take_samples)
+    rate_samples: VecDeque<RateSample>,
+    /// Total bytes delivered as of recent acknowledgement batches, oldest first, for `goodput`
+    delivery_history: VecDeque<(Instant, u64)>,
+    #[cfg(test)]
+    window_override: Option<u64>,
     random_number_generator: rand::rngs::StdRng,
 }
 
 impl Bbr {
     /// Construct a state using the given `config` and current time `now`
     pub fn new(config: Arc<BbrConfig>, current_mtu: u16) -> Self {
-        let initial_window = config.initial_window;
-        Self {
+        let initial_window = config.effective_initial_window();
+        let prior_state = config.prior_state;
+        let min_rtt = config.initial_rtt.unwrap_or_default();
+        let startup_pacing_gain = config.startup_pacing_gain;
+        let max_bandwidth = BandwidthEstimation::new(config.bandwidth_window_rounds);
+        let mut bbr = Self {
             config,
             current_mtu: current_mtu as u64,
-            max_bandwidth: BandwidthEstimation::default(),
+            max_bandwidth,
             acked_bytes: 0,
-            mode: Mode::Startup,
+            sent_bytes: 0,
+            lost_bytes: 0,
+            mode: BbrMode::Startup,
             loss_state: Default::default(),
             recovery_state: RecoveryState::NotInRecovery,
             recovery_window: 0,
+            recovery_clean_rounds: 0,
             is_at_full_bandwidth: false,
-            pacing_gain: K_DEFAULT_HIGH_GAIN,
-            high_gain: K_DEFAULT_HIGH_GAIN,
-            drain_gain: 1.0 / K_DEFAULT_HIGH_GAIN,
-            cwnd_gain: K_DEFAULT_HIGH_GAIN,
-            high_cwnd_gain: K_DEFAULT_HIGH_GAIN,
-            last_cycle_start: None,
+            pacing_gain: startup_pacing_gain,
+            high_gain: startup_pacing_gain,
+            cwnd_gain: K_STARTUP_CWND_GAIN,
+            high_cwnd_gain: K_STARTUP_CWND_GAIN,
+            startup_started_at: None,
+            drain_round_count: 0,
             current_cycle_offset: 0,
+            cycle_start_round: 0,
+            cycle_count: 0,
             init_cwnd: initial_window,
             min_cwnd: calculate_min_window(current_mtu as u64),
             prev_in_flight_count: 0,
             exit_probe_rtt_at: None,
             probe_rtt_last_started_at: None,
-            min_rtt: Default::default(),
+            min_rtt,
+            min_rtt_valid: false,
+            latest_rtt: Duration::ZERO,
+            smoothed_rtt: Duration::ZERO,
             exiting_quiescence: false,
             pacing_rate: 0,
             max_acked_packet_number: 0,
             max_sent_packet_number: 0,
             end_recovery_at_packet_number: 0,
             cwnd: initial_window,
-            current_round_trip_end_packet_number: 0,
+            current_round_trip_start: None,
+            is_round_trip_end_acked: false,
             round_count: 0,
+            round_start_cwnd: initial_window,
             bw_at_last_round: 0,
             round_wo_bw_gain: 0,
+            lossy_round_count: 0,
+            stalled_round_count: 0,
+            round_max_delivery_rate: 0,
+            full_sample_rounds: 0,
+            bandwidth_drop: BandwidthDropState::default(),
             ack_aggregation: AckAggregationState::default(),
+            burst: BurstState::default(),
+            mode_durations: ModeDurations::default(),
+            mode_durations_updated_at: None,
+            aimd: None,
+            rate_samples: VecDeque::new(),
+            delivery_history: VecDeque::new(),
+            #[cfg(test)]
+            window_override: None,
             random_number_generator: rand::rngs::StdRng::from_os_rng(),
+        };
+        if let Some(state) = prior_state {
+            bbr.min_rtt = state.min_rtt;
+            bbr.max_bandwidth.seed(state.bandwidth);
+            bbr.init_cwnd = bbr.get_target_cwnd(1.0).min(bbr.config.max_window);
+            bbr.cwnd = bbr.init_cwnd;
+            bbr.round_start_cwnd = bbr.cwnd;
+            if bbr.config.skip_startup {
+                // Pace at the seeded bandwidth until the first gain cycle phase ends
+                bbr.is_at_full_bandwidth = true;
+                bbr.mode = BbrMode::ProbeBw;
+                bbr.cwnd_gain = bbr.config.probe_bw_cwnd_gain;
+                bbr.current_cycle_offset = bbr.pacing_gain_cycle().len() - 1;
+                bbr.pacing_gain = bbr.pacing_gain_cycle_phase(bbr.current_cycle_offset);
+            }
+        }
+        bbr
+    }
+
+    /// Construct a state whose lifetime, and hence STARTUP, begins at `now`
+    ///
+    /// The controller never reads the clock itself, so tests can drive it with synthetic time
+    /// from the very start rather than from the first acknowledgement.
+    #[cfg(test)]
+    pub(crate) fn new_at(config: Arc<BbrConfig>, current_mtu: u16, now: Instant) -> Self {
+        let mut bbr = Self::new(config, current_mtu);
+        if bbr.mode == BbrMode::Startup {
+            bbr.startup_started_at = Some(now);
+        }
+        bbr.mode_durations_updated_at = Some(now);
+        bbr
+    }
+
+    /// Estimated bottleneck bandwidth
+    pub fn bandwidth(&self) -> Bandwidth {
+        self.max_bandwidth.get_estimate()
+    }
+
+    /// How well the bandwidth estimate is backed by measurements, from 0.0 to 1.0
+    ///
+    /// The fraction of the rounds in the bandwidth filter window (see
+    /// [`BbrConfig::bandwidth_window_rounds`]) which yielded a delivery rate sample that wasn't
+    /// application-limited. Estimates seeded from a [`BbrState`] or measured only while
+    /// application-limited have a confidence of 0.0. Consumers should check this before relying
+    /// on the estimate; [`export_state`](Self::export_state) does so already.
+    pub fn bandwidth_confidence(&self) -> f64 {
+        self.full_sample_rounds as f64 / self.config.bandwidth_window_rounds as f64
+    }
+
+    /// The windowed maximum filter behind [`bandwidth`](Self::bandwidth), for inspection by tests
+    #[cfg(test)]
+    pub(crate) fn bandwidth_filter(&self) -> &MinMax {
+        self.max_bandwidth.max_filter()
+    }
+
+    /// Capture the path model so that a later connection to the same peer can resume from it
+    ///
+    /// Returns `None` while the bandwidth estimate isn't backed by any full delivery rate sample
+    /// (see [`bandwidth_confidence`](Self::bandwidth_confidence)), as a later connection would
+    /// skip STARTUP based on an estimate that was never measured. See [`BbrConfig::prior_state`].
+    pub fn export_state(&self) -> Option<BbrState> {
+        if self.bandwidth_confidence() == 0.0 {
+            return None;
+        }
+        Some(BbrState {
+            min_rtt: self.min_rtt,
+            bandwidth: self.max_bandwidth.get_estimate().as_bytes_per_sec(),
+        })
+    }
+
+    /// The static tuning in effect for this controller, including any configuration overrides
+    pub fn parameters(&self) -> BbrParameters {
+        BbrParameters {
+            high_gain: self.high_gain,
+            pacing_gain_cycle: (0..self.pacing_gain_cycle().len())
+                .map(|offset| self.pacing_gain_cycle_phase(offset))
+                .collect(),
+            min_window_packets: K_MIN_WINDOW_PACKETS,
+            probe_rtt_interval: self.config.probe_rtt_interval,
+        }
+    }
+
+    /// A fair share of the current congestion window for one of `active_streams` streams
+    ///
+    /// Useful for sizing the initial buffers of a newly opened stream. Never less than one
+    /// full-sized packet, so that no stream is starved outright.
+    pub fn recommended_stream_window(&self, active_streams: u32) -> u64 {
+        let share = self.window() / u64::from(active_streams.max(1));
+        share.max(self.current_mtu)
+    }
+
+    /// Mark the start of a burst of application-paced sends
+    ///
+    /// Credits the pacing budget for the time elapsed since it was last updated.
+    pub fn begin_burst(&mut self, now: Instant) {
+        self.refill_pacing_budget(now);
+    }
+
+    /// Mark the end of a burst which sent `bytes` since the matching [`begin_burst`](Self::begin_burst)
+    pub fn end_burst(&mut self, now: Instant, bytes: u64) {
+        self.refill_pacing_budget(now);
+        self.burst.budget = self.burst.budget.saturating_sub(bytes);
+    }
+
+    /// Number of bytes which may currently be sent without exceeding the pacing rate
+    pub fn pacing_budget(&self) -> u64 {
+        self.burst.budget
+    }
+
+    fn refill_pacing_budget(&mut self, now: Instant) {
+        let capacity = self.window();
+        self.burst.budget = match self.burst.updated_at {
+            Some(updated_at) => {
+                let elapsed = now.saturating_duration_since(updated_at);
+                let refill = self.pacing_rate as u128 * elapsed.as_nanos() / 1_000_000_000;
+                (self.burst.budget as u128 + refill).min(capacity as u128) as u64
+            }
+            // Start out with a full budget
+            None => capacity,
+        };
+        self.burst.updated_at = Some(now);
+    }
+
+    /// Name of a congestion controller expected to perform better on this path, if any
+    ///
+    /// Currently suggests `"cubic"` once a traffic policer is suspected, i.e. heavy loss has
+    /// persisted for several consecutive rounds while probing bandwidth. BBR cannot tame such
+    /// policers, whereas a loss-based controller backs off in response. Acting on the suggestion
+    /// is left to a supervising layer.
+    pub fn suggest_switch(&self) -> Option<&'static str> {
+        match self.mode == BbrMode::ProbeBw && self.lossy_round_count >= K_POLICER_DETECTION_ROUNDS
+        {
+            true => Some("cubic"),
+            false => None,
         }
     }
 
+    /// The controller's current mode
+    pub fn mode(&self) -> BbrMode {
+        self.mode
+    }
+
+    /// The gains currently applied to the bandwidth estimate and the BDP
+    pub fn gains(&self) -> Gains {
+        Gains {
+            pacing: self.pacing_gain,
+            cwnd: match self.mode {
+                BbrMode::ProbeRtt => self.config.probe_rtt_cwnd_gain,
+                _ => self.cwnd_gain,
+            },
+        }
+    }
+
+    /// The phase of the PROBE_BW gain cycle, or `None` in other modes
+    pub fn probe_bw_phase(&self) -> Option<ProbeBwPhase> {
+        if self.mode != BbrMode::ProbeBw {
+            return None;
+        }
+        Some(match self.pacing_gain {
+            gain if gain > 1.0 => ProbeBwPhase::Up,
+            gain if gain < 1.0 => ProbeBwPhase::Down,
+            _ => ProbeBwPhase::Unity,
+        })
+    }
+
+    /// Extra window allowance compensating for acknowledgements arriving in aggregated bursts
+    ///
+    /// Once STARTUP has found the path's bandwidth, this is the largest recent excess of
+    /// acknowledged bytes over what the estimated bandwidth would have delivered, and is included
+    /// in the congestion window. Limited by [`BbrConfig::extra_acked_cap`].
+    pub fn extra_acked(&self) -> u64 {
+        self.cap_extra_acked(self.ack_aggregation.max_ack_height.get())
+    }
+
+    /// Limit ack aggregation compensation to the configured multiple of the estimated BDP
+    fn cap_extra_acked(&self, extra_acked: u64) -> u64 {
+        extra_acked.min(self.get_target_cwnd(self.config.extra_acked_cap))
+    }
+
+    /// Number of bytes the controller currently aims to keep in flight
+    ///
+    /// In PROBE_RTT this is the reduced window that the bytes in flight must drain to before the
+    /// minimum RTT can be measured; otherwise it is the estimated BDP scaled by the pacing gain.
+    pub fn inflight_target(&self) -> u64 {
+        match self.mode {
+            BbrMode::ProbeRtt => self.get_probe_rtt_cwnd(),
+            _ => self.get_target_cwnd(self.pacing_gain),
+        }
+    }
+
+    /// Total number of bytes acknowledged over the lifetime of the controller
+    pub fn delivered_bytes(&self) -> u64 {
+        self.acked_bytes
+    }
+
+    /// Total number of bytes declared lost over the lifetime of the controller
+    pub fn lost_bytes(&self) -> u64 {
+        self.lost_bytes
+    }
+
+    /// Total number of bytes sent over the lifetime of the controller
+    pub fn sent_bytes(&self) -> u64 {
+        self.sent_bytes
+    }
+
+    /// Gap between consecutive full-sized packets at the current pacing rate, if pacing
+    ///
+    /// Used by [`PacingMode::PacketInterval`].
+    pub fn packet_interval(&self) -> Option<Duration> {
+        if self.pacing_rate == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            (u128::from(self.current_mtu) * 1_000_000_000 / u128::from(self.pacing_rate))
+                .try_into()
+                .unwrap_or(u64::MAX),
+        ))
+    }
+
+    /// Bytes delivered per second over the trailing `window`, up to the latest acknowledgement
+    ///
+    /// Unlike the [bandwidth estimate](Self::bandwidth), which is the peak delivery rate, this is
+    /// the average rate at which data actually got through, e.g. for reporting application
+    /// throughput. `window` is limited to [`BbrConfig::goodput_history`]; returns 0 if that is
+    /// zero or `window` is.
+    pub fn goodput(&self, window: Duration) -> u64 {
+        let window = window.min(self.config.goodput_history);
+        let Some(&(latest, delivered)) = self.delivery_history.back() else {
+            return 0;
+        };
+        if window.is_zero() {
+            return 0;
+        }
+        // Bytes delivered by the start of the window, which precedes the history if the
+        // controller is younger than `window`
+        let start = latest.checked_sub(window);
+        let baseline = match self
+            .delivery_history
+            .partition_point(|&(time, _)| start.is_some_and(|start| time <= start))
+        {
+            0 => 0,
+            i => self.delivery_history[i - 1].1,
+        };
+        BandwidthEstimation::bw_from_delta(delivered - baseline, window).unwrap_or(0)
+    }
+
+    /// Remove and return the delivery rate samples recorded since the last call, oldest first
+    ///
+    /// Unlike [`ControllerMetrics::delivery_rate`], which only reflects the latest sample, this
+    /// preserves the history between polls, e.g. for qlog export. Only the most recent
+    /// [`BbrConfig::rate_sample_history`] samples are kept.
+    pub fn take_samples(&mut self) -> Vec<RateSample> {
+        self.rate_samples.drain(..).collect()
+    }
+
+    /// Force `window()` to return `value` regardless of the controller's state, or stop doing so
+    ///
+    /// Lets tests exercise sender backpressure deterministically.
+    #[cfg(test)]
+    pub(crate) fn set_window_override(&mut self, value: Option<u64>) {
+        self.window_override = value;
+    }
+
+    /// Cumulative time spent in each mode, up to the most recent acknowledgement
+    pub fn mode_durations(&self) -> ModeDurations {
+        self.mode_durations
+    }
+
+    /// Whether the window has been below the bytes in flight for several consecutive rounds
+    ///
+    /// While stalled, nothing can be sent until acknowledgements free up space in the window.
+    pub fn is_stalled(&self) -> bool {
+        self.stalled_round_count >= K_STALLED_ROUNDS
+    }
+
+    fn update_stall_state(&mut self, in_flight: u64) {
+        // ProbeRtt deliberately shrinks the window below the bytes in flight.
+        if self.mode == BbrMode::ProbeRtt || self.window() >= in_flight {
+            self.stalled_round_count = 0;
+            return;
+        }
+        self.stalled_round_count += 1;
+        if self.stalled_round_count > K_MAX_STALLED_ROUNDS {
+            // Release a packet so that a collapsed window can't starve the sender indefinitely.
+            let window = in_flight + self.current_mtu;
+            self.cwnd = self.cwnd.max(window).min(self.config.max_window);
+            if self.recovery_state.in_recovery() {
+                self.recovery_window = self.recovery_window.max(window);
+            }
+            self.stalled_round_count = 0;
+        }
+    }
+
+    /// Drop the bandwidth estimate right away if the delivery rate has stayed well below it for
+    /// several rounds, rather than waiting for the max filter window to expire
+    fn check_bandwidth_drop(&mut self) {
+        let delivery_rate = mem::take(&mut self.round_max_delivery_rate);
+        let estimate = self.max_bandwidth.get_estimate().as_bytes_per_sec();
+        if delivery_rate == 0 || delivery_rate as f64 >= estimate as f64 * K_BANDWIDTH_DROP_RATIO {
+            self.bandwidth_drop = BandwidthDropState::default();
+            return;
+        }
+        let drop = &mut self.bandwidth_drop;
+        drop.rounds += 1;
+        drop.max_delivery_rate = drop.max_delivery_rate.max(delivery_rate);
+        if drop.rounds >= K_BANDWIDTH_DROP_ROUNDS {
+            self.max_bandwidth
+                .reset_estimate(self.round_count, drop.max_delivery_rate);
+            self.full_sample_rounds = drop.rounds;
+            self.bandwidth_drop = BandwidthDropState::default();
+        }
+    }
+
+    fn set_mode(&mut self, mode: BbrMode) {
+        if self.mode == mode {
+            return;
+        }
+        self.mode = mode;
+        if let Some(hook) = &self.config.on_mode_change {
+            (hook.0)(mode);
+        }
+    }
+
+    /// Catch inconsistent model updates early in debug builds
+    fn debug_check_invariants(&self) {
+        debug_assert!(
+            self.cwnd >= self.min_cwnd.min(self.config.max_window),
+            "cwnd {} below minimum {}",
+            self.cwnd,
+            self.min_cwnd
+        );
+        debug_assert!(self.cwnd <= self.config.max_window);
+        debug_assert!(self.window() <= self.config.max_window);
+        debug_assert!(self.pacing_gain > 0.0 && self.pacing_gain.is_finite());
+        debug_assert!(self.cwnd_gain > 0.0 && self.cwnd_gain.is_finite());
+        debug_assert!(
+            !self.recovery_state.in_recovery()
+                || self.recovery_window == 0
+                || self.recovery_window >= self.min_cwnd,
+            "recovery window {} below minimum {}",
+            self.recovery_window,
+            self.min_cwnd
+        );
+        debug_assert!(
+            !matches!(self.mode, BbrMode::Drain | BbrMode::ProbeBw) || self.is_at_full_bandwidth,
+            "{:?} without having reached full bandwidth",
+            self.mode
+        );
+    }
+
+    /// Forget that the pipe was ever found to be full and probe for bandwidth in STARTUP
+    ///
+    /// This is the only way for the full bandwidth detection to be reset; it otherwise stays
+    /// latched through the ups and downs of PROBE_BW.
+    fn restart_startup(&mut self) {
+        self.is_at_full_bandwidth = false;
+        self.bw_at_last_round = 0;
+        self.round_wo_bw_gain = 0;
+        self.enter_startup_mode();
+    }
+
     fn enter_startup_mode(&mut self) {
-        self.mode = Mode::Startup;
+        self.set_mode(BbrMode::Startup);
+        self.startup_started_at = None;
         self.pacing_gain = self.high_gain;
         self.cwnd_gain = self.high_cwnd_gain;
+        self.calculate_pacing_rate();
+    }
+
+    fn enter_drain_mode(&mut self) {
+        self.set_mode(BbrMode::Drain);
+        // Drain the queue built up in STARTUP within about one round trip
+        self.pacing_gain = 1.0 / self.high_gain;
+        self.cwnd_gain = self.high_cwnd_gain;
+        self.drain_round_count = 0;
+        // Slow down right away rather than with the next acknowledgement
+        self.calculate_pacing_rate();
     }
 
-    fn enter_probe_bandwidth_mode(&mut self, now: Instant) {
-        self.mode = Mode::ProbeBw;
-        self.cwnd_gain = K_DERIVED_HIGH_CWNDGAIN;
-        self.last_cycle_start = Some(now);
-        // Pick a random offset for the gain cycle out of {0, 2..7} range. 1 is
-        // excluded because in that case increased gain and decreased gain would not
-        // follow each other.
+    fn enter_probe_bandwidth_mode(&mut self) {
+        self.set_mode(BbrMode::ProbeBw);
+        self.cwnd_gain = self.config.probe_bw_cwnd_gain;
+        self.cycle_start_round = self.round_count;
+        // Pick a random offset into the configured gain cycle of length n, out of
+        // {0, 2..n-1}. 1 is excluded because in that case increased gain and decreased
+        // gain would not follow each other.
         let mut rand_index = self
             .random_number_generator
-            .random_range(0..K_PACING_GAIN.len() as u8 - 1);
+            .random_range(0..self.pacing_gain_cycle().len() - 1);
         if rand_index >= 1 {
             rand_index += 1;
         }
         self.current_cycle_offset = rand_index;
-        self.pacing_gain = K_PACING_GAIN[rand_index as usize];
+        self.pacing_gain = self.pacing_gain_cycle_phase(rand_index);
+        self.calculate_pacing_rate();
+    }
+
+    fn pacing_gain_cycle(&self) -> &[f32] {
+        self.config
+            .pacing_gain_cycle
+            .as_deref()
+            .unwrap_or(&K_PACING_GAIN)
     }
 
-    fn update_recovery_state(&mut self, is_round_start: bool) {
-        // Exit recovery when there are no losses for a round.
+    /// Pacing gain of the gain cycle phase at `offset`, limited to the configured maximum
+    fn pacing_gain_cycle_phase(&self, offset: usize) -> f32 {
+        let gain = self.pacing_gain_cycle()[offset];
+        match self.config.max_probe_pacing_gain {
+            Some(max) => gain.min(max),
+            None => gain,
+        }
+    }
+
+    fn update_recovery_state(&mut self, now: Instant, is_round_start: bool) {
+        // Exit recovery when there are no losses for `recovery_exit_rounds` rounds.
         if self.loss_state.has_losses() {
             self.end_recovery_at_packet_number = self.max_sent_packet_number;
+            self.recovery_clean_rounds = 0;
         }
         match self.recovery_state {
             // Enter conservation on the first loss.
@@ -138,29 +590,35 @@ impl Bbr {
                 self.recovery_window = 0;
                 // Since the conservation phase is meant to be lasting for a whole
                 // round, extend the current round as if it were started right now.
-                self.current_round_trip_end_packet_number = self.max_sent_packet_number;
+                self.current_round_trip_start = Some(now);
             }
             RecoveryState::Growth | RecoveryState::Conservation => {
                 if self.recovery_state == RecoveryState::Conservation && is_round_start {
                     self.recovery_state = RecoveryState::Growth;
                 }
-                // Exit recovery if appropriate.
+                // Exit recovery if appropriate. The first clean round ends once everything in
+                // flight at the time of the last loss has been acknowledged.
                 if !self.loss_state.has_losses()
                     && self.max_acked_packet_number > self.end_recovery_at_packet_number
                 {
-                    self.recovery_state = RecoveryState::NotInRecovery;
+                    if self.recovery_clean_rounds == 0 || is_round_start {
+                        self.recovery_clean_rounds += 1;
+                    }
+                    if self.recovery_clean_rounds >= self.config.recovery_exit_rounds {
+                        self.recovery_state = RecoveryState::NotInRecovery;
+                        self.recovery_window = 0;
+                    }
                 }
             }
             _ => {}
         }
     }
 
-    fn update_gain_cycle_phase(&mut self, now: Instant, in_flight: u64) {
-        // In most cases, the cycle is advanced after an RTT passes.
-        let mut should_advance_gain_cycling = self
-            .last_cycle_start
-            .map(|last_cycle_start| now.duration_since(last_cycle_start) > self.min_rtt)
-            .unwrap_or(false);
+    fn update_gain_cycle_phase(&mut self, in_flight: u64) {
+        // In most cases, the cycle is advanced once a round trip's worth of data has been
+        // delivered. Counting rounds rather than elapsed multiples of min_rtt keeps the phases in
+        // step with the feedback actually received when the RTT exceeds its minimum.
+        let mut should_advance_gain_cycling = self.round_count > self.cycle_start_round;
         // If the pacing gain is above 1.0, the connection is trying to probe the
         // bandwidth by increasing the number of bytes in flight to at least
         // pacing_gain * BDP.  Make sure that it actually reaches the target, as
@@ -183,38 +641,115 @@ impl Bbr {
         }
 
         if should_advance_gain_cycling {
-            self.current_cycle_offset = (self.current_cycle_offset + 1) % K_PACING_GAIN.len() as u8;
-            self.last_cycle_start = Some(now);
+            let pacing_gain = self.pacing_gain;
+            self.advance_gain_cycle_phase();
             // Stay in low gain mode until the target BDP is hit.  Low gain mode
             // will be exited immediately when the target BDP is achieved.
             if DRAIN_TO_TARGET
-                && self.pacing_gain < 1.0
-                && (K_PACING_GAIN[self.current_cycle_offset as usize] - 1.0).abs() < f32::EPSILON
+                && pacing_gain < 1.0
+                && (self.pacing_gain - 1.0).abs() < f32::EPSILON
                 && in_flight > self.get_target_cwnd(1.0)
             {
-                return;
+                self.pacing_gain = pacing_gain;
             }
-            self.pacing_gain = K_PACING_GAIN[self.current_cycle_offset as usize];
         }
     }
 
+    fn advance_gain_cycle_phase(&mut self) {
+        let offset = (self.current_cycle_offset + 1) % self.pacing_gain_cycle().len();
+        self.pacing_gain = self.pacing_gain_cycle_phase(offset);
+        self.current_cycle_offset = offset;
+        self.cycle_count += 1;
+        self.cycle_start_round = self.round_count;
+    }
+
     fn maybe_exit_startup_or_drain(&mut self, now: Instant, in_flight: u64) {
-        if self.mode == Mode::Startup && self.is_at_full_bandwidth {
-            self.mode = Mode::Drain;
-            self.pacing_gain = self.drain_gain;
-            self.cwnd_gain = self.high_cwnd_gain;
+        if self.mode == BbrMode::Startup {
+            // Don't rely on the bandwidth plateauing: pathological sample patterns could
+            // otherwise keep the connection in STARTUP indefinitely.
+            let started_at = *self.startup_started_at.get_or_insert(now);
+            if now.saturating_duration_since(started_at) >= self.config.max_startup_duration {
+                self.is_at_full_bandwidth = true;
+            }
         }
-        if self.mode == Mode::Drain && in_flight <= self.get_target_cwnd(1.0) {
-            self.enter_probe_bandwidth_mode(now);
+        if self.mode == BbrMode::Startup && self.is_at_full_bandwidth {
+            self.enter_drain_mode();
+        }
+        // Competing traffic may keep the bytes in flight above the BDP indefinitely, so don't
+        // wait for the queue to drain completely.
+        if self.mode == BbrMode::Drain
+            && (self.is_queue_drained(in_flight)
+                || self.drain_round_count >= self.config.max_drain_rounds)
+        {
+            self.enter_probe_bandwidth_mode();
         }
     }
 
+    fn is_queue_drained(&self, in_flight: u64) -> bool {
+        match self.config.drain_rtt_tolerance {
+            // The RTT only returns to the minimum once the queue is gone
+            Some(tolerance) => {
+                self.smoothed_rtt.as_secs_f64()
+                    <= self.min_rtt.as_secs_f64() * (1.0 + tolerance as f64)
+            }
+            None => in_flight <= self.get_target_cwnd(1.0),
+        }
+    }
+
+    /// Whether `rtt` is implausibly far below the current minimum RTT, e.g. due to a bogus ack
+    /// delay, and shouldn't replace it
+    fn is_rtt_outlier(&self, rtt: Duration) -> bool {
+        self.min_rtt_valid
+            && self
+                .config
+                .min_rtt_floor
+                .is_some_and(|floor| rtt.as_secs_f64() < self.min_rtt.as_secs_f64() * floor as f64)
+    }
+
+    fn record_delivery(&mut self, now: Instant) {
+        let horizon = self.config.goodput_history;
+        if horizon.is_zero() {
+            return;
+        }
+        match self.delivery_history.back_mut() {
+            // Coalesce closely spaced batches to bound the size of the history
+            Some((time, delivered))
+                if now.saturating_duration_since(*time) < K_GOODPUT_GRANULARITY =>
+            {
+                *delivered = self.acked_bytes;
+            }
+            _ => self.delivery_history.push_back((now, self.acked_bytes)),
+        }
+        // Keep the newest entry at least `horizon` old as the baseline for the longest window
+        while self
+            .delivery_history
+            .get(1)
+            .is_some_and(|&(time, _)| now.saturating_duration_since(time) >= horizon)
+        {
+            self.delivery_history.pop_front();
+        }
+    }
+
+    fn record_rate_sample(&mut self, now: Instant, sample: DeliveryRateSample) {
+        let capacity = self.config.rate_sample_history;
+        if capacity == 0 {
+            return;
+        }
+        if self.rate_samples.len() == capacity {
+            self.rate_samples.pop_front();
+        }
+        self.rate_samples.push_back(RateSample {
+            time: now,
+            delivery_rate: Bandwidth::from_bytes_per_sec(sample.rate),
+            app_limited: sample.app_limited,
+        });
+    }
+
     fn is_min_rtt_expired(&self, now: Instant, app_limited: bool) -> bool {
         !app_limited
-            && self
-                .probe_rtt_last_started_at
-                .map(|last| now.saturating_duration_since(last) > Duration::from_secs(10))
-                .unwrap_or(true)
+            && self.probe_rtt_last_started_at.is_some_and(|last| {
+                now.saturating_duration_since(last) > self.config.probe_rtt_interval
+            })
     }
 
     fn maybe_enter_or_exit_probe_rtt(
@@ -225,16 +760,23 @@ impl Bbr {
         app_limited: bool,
     ) {
         let min_rtt_expired = self.is_min_rtt_expired(now, app_limited);
-        if min_rtt_expired && !self.exiting_quiescence && self.mode != Mode::ProbeRtt {
-            self.mode = Mode::ProbeRtt;
+        // Defer ProbeRtt until recovery ends: the recovery window already governs the bytes in
+        // flight, and shrinking it further would only compound the loss response.
+        if min_rtt_expired
+            && !self.exiting_quiescence
+            && self.recovery_state == RecoveryState::NotInRecovery
+            && self.mode != BbrMode::ProbeRtt
+        {
+            self.set_mode(BbrMode::ProbeRtt);
             self.pacing_gain = 1.0;
+            self.calculate_pacing_rate();
             // Do not decide on the time to exit ProbeRtt until the
             // |bytes_in_flight| is at the target small value.
             self.exit_probe_rtt_at = None;
             self.probe_rtt_last_started_at = Some(now);
         }
 
-        if self.mode == Mode::ProbeRtt {
+        if self.mode == BbrMode::ProbeRtt {
             if self.exit_probe_rtt_at.is_none() {
                 // If the window has reached the appropriate size, schedule exiting
                 // ProbeRtt.  The CWND during ProbeRtt is
@@ -248,7 +790,7 @@ impl Bbr {
                 if !self.is_at_full_bandwidth {
                     self.enter_startup_mode();
                 } else {
-                    self.enter_probe_bandwidth_mode(now);
+                    self.enter_probe_bandwidth_mode();
                 }
             }
         }
@@ -257,10 +799,9 @@ impl Bbr {
     }
 
     fn get_target_cwnd(&self, gain: f32) -> u64 {
-        let bw = self.max_bandwidth.get_estimate();
+        let bw = self.max_bandwidth.get_estimate().as_bytes_per_sec();
         let bdp = self.min_rtt.as_micros() as u64 * bw;
-        let bdpf = bdp as f64;
-        let cwnd = ((gain as f64 * bdpf) / 1_000_000f64) as u64;
+        let cwnd = apply_gain(bdp, gain) / 1_000_000;
         // BDP estimate will be zero if no bandwidth samples are available yet.
         if cwnd == 0 {
             return self.init_cwnd;
@@ -269,50 +810,51 @@ impl Bbr {
     }
 
     fn get_probe_rtt_cwnd(&self) -> u64 {
-        const K_MODERATE_PROBE_RTT_MULTIPLIER: f32 = 0.75;
-        if PROBE_RTT_BASED_ON_BDP {
-            return self.get_target_cwnd(K_MODERATE_PROBE_RTT_MULTIPLIER);
+        if PROBE_RTT_BASED_ON_BDP && !self.config.conservative_probe_rtt {
+            return self.get_target_cwnd(self.config.probe_rtt_cwnd_gain);
         }
         self.min_cwnd
     }
 
     fn calculate_pacing_rate(&mut self) {
-        let bw = self.max_bandwidth.get_estimate();
+        let bw = self.max_bandwidth.get_estimate().as_bytes_per_sec();
         if bw == 0 {
             return;
         }
-        let target_rate = (bw as f64 * self.pacing_gain as f64) as u64;
+        let target_rate = apply_gain(bw, self.pacing_gain);
         if self.is_at_full_bandwidth {
-            self.pacing_rate = target_rate;
-            return;
-        }
-
-        // Pace at the rate of initial_window / RTT as soon as RTT measurements are
-        // available.
-        if self.pacing_rate == 0 && self.min_rtt.as_nanos() != 0 {
+            self.pacing_rate = match self.config.pacing_smoothing {
+                // Approach the target gradually to avoid bursts when the estimate or gain changes
+                Some(weight) if self.pacing_rate != 0 => {
+                    let rate = self.pacing_rate as f64;
+                    (rate + (target_rate as f64 - rate) * weight as f64) as u64
+                }
+                _ => target_rate,
+            };
+        } else if self.pacing_rate == 0 && self.min_rtt.as_nanos() != 0 {
+            // Pace at the rate of initial_window / RTT as soon as RTT measurements are
+            // available.
             self.pacing_rate =
                 BandwidthEstimation::bw_from_delta(self.init_cwnd, self.min_rtt).unwrap();
-            return;
-        }
-
-        // Do not decrease the pacing rate during startup.
-        if self.pacing_rate < target_rate {
+        } else if self.pacing_rate < target_rate {
+            // Do not decrease the pacing rate during startup.
             self.pacing_rate = target_rate;
         }
+        self.pacing_rate = self.pacing_rate.max(self.config.min_pacing_rate);
     }
 
     fn calculate_cwnd(&mut self, bytes_acked: u64, excess_acked: u64) {
-        if self.mode == Mode::ProbeRtt {
+        if self.mode == BbrMode::ProbeRtt {
             return;
         }
         let mut target_window = self.get_target_cwnd(self.cwnd_gain);
         if self.is_at_full_bandwidth {
             // Add the max recently measured ack aggregation to CWND.
-            target_window += self.ack_aggregation.max_ack_height.get();
+            target_window += self.extra_acked();
         } else {
             // Add the most recent excess acked.  Because CWND never decreases in
             // STARTUP, this will automatically create a very localized max filter.
-            target_window += excess_acked;
+            target_window += self.cap_extra_acked(excess_acked);
         }
         // Instead of immediately setting the target CWND as the new one, BBR grows
         // the CWND towards |target_window| by only increasing it |bytes_acked| at a
@@ -323,21 +865,28 @@ impl Bbr {
             // If the connection is not yet out of startup phase, do not decrease
             // the window.
             self.cwnd += bytes_acked;
+            if let Some(growth) = self.config.max_startup_growth {
+                let max_cwnd = apply_gain(self.round_start_cwnd, growth);
+                self.cwnd = self.cwnd.min(max_cwnd.max(self.round_start_cwnd));
+            }
         }
 
         // Enforce the limits on the congestion window.
         if self.cwnd < self.min_cwnd {
             self.cwnd = self.min_cwnd;
         }
+        self.cwnd = self.cwnd.min(self.config.max_window);
     }
 
     fn calculate_recovery_window(&mut self, bytes_acked: u64, bytes_lost: u64, in_flight: u64) {
         if !self.recovery_state.in_recovery() {
             return;
         }
-        // Set up the initial recovery window.
+        // Set up the initial recovery window as a multiplicative reduction of the congestion
+        // window.
         if self.recovery_window == 0 {
-            self.recovery_window = self.min_cwnd.max(in_flight + bytes_acked);
+            let reduced = apply_gain(self.cwnd, self.config.recovery_beta);
+            self.recovery_window = reduced.max(self.min_cwnd);
             return;
         }
 
@@ -368,8 +917,8 @@ impl Bbr {
         if app_limited {
             return;
         }
-        let target = (self.bw_at_last_round as f64 * K_STARTUP_GROWTH_TARGET as f64) as u64;
-        let bw = self.max_bandwidth.get_estimate();
+        let target = apply_gain(self.bw_at_last_round, self.config.startup_growth_target);
+        let bw = self.max_bandwidth.get_estimate().as_bytes_per_sec();
         if bw >= target {
             self.bw_at_last_round = bw;
             self.round_wo_bw_gain = 0;
@@ -378,7 +927,7 @@ impl Bbr {
         }
 
         self.round_wo_bw_gain += 1;
-        if self.round_wo_bw_gain >= K_ROUND_TRIPS_WITHOUT_GROWTH_BEFORE_EXITING_STARTUP as u64
+        if self.round_wo_bw_gain >= self.config.startup_full_bw_rounds
             || (self.recovery_state.in_recovery())
         {
             self.is_at_full_bandwidth = true;
@@ -386,10 +935,41 @@ impl Bbr {
     }
 }
 
+/// One-line summary of the controller's state, more readable than the `Debug` output
+impl fmt::Display for Bbr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} cwnd={} bdp={} min_rtt={:?} bw={}B/s pacing_gain={}",
+            self.mode,
+            self.window(),
+            self.get_target_cwnd(1.0),
+            self.min_rtt,
+            self.max_bandwidth.get_estimate().as_bytes_per_sec(),
+            self.pacing_gain,
+        )?;
+        if self.recovery_state.in_recovery() {
+            write!(f, " recovery_window={}", self.recovery_window)?;
+        }
+        if self.config.max_window != u64::MAX {
+            write!(f, " max_window={}", self.config.max_window)?;
+        }
+        Ok(())
+    }
+}
+
 impl Controller for Bbr {
     fn on_sent(&mut self, now: Instant, bytes: u64, last_packet_number: u64) {
-        self.max_sent_packet_number = last_packet_number;
+        // Packet numbers only increase, but guard against callers reporting them out of order, which
+        // would otherwise end recovery before the packets in flight when it began are acknowledged.
+        self.max_sent_packet_number = self.max_sent_packet_number.max(last_packet_number);
+        self.sent_bytes += bytes;
         self.max_bandwidth.on_sent(now, bytes);
+        if self.config.hybrid_cwnd && self.aimd.is_none() {
+            let mut config = NewRenoConfig::default();
+            config.initial_window(self.init_cwnd);
+            self.aimd = Some(NewReno::new(Arc::new(config), now, self.current_mtu as u16));
+        }
     }
 
     fn on_ack(
@@ -400,14 +980,76 @@ impl Controller for Bbr {
         app_limited: bool,
         rtt: &RttEstimator,
     ) {
-        self.max_bandwidth
+        if let Some(aimd) = &mut self.aimd {
+            aimd.on_ack(now, sent, bytes, app_limited, rtt);
+        }
+        let sample = self
+            .max_bandwidth
             .on_ack(now, sent, bytes, self.round_count, app_limited);
+        if let Some(sample) = sample {
+            if !sample.app_limited {
+                self.round_max_delivery_rate = self.round_max_delivery_rate.max(sample.rate);
+            }
+            self.record_rate_sample(now, sample);
+        }
         self.acked_bytes += bytes;
-        if self.is_min_rtt_expired(now, app_limited) || self.min_rtt > rtt.min() {
-            self.min_rtt = rtt.min();
+        // The first sample starts the min_rtt filter window, so that it expires one probe
+        // interval later rather than immediately.
+        self.probe_rtt_last_started_at.get_or_insert(now);
+        // A round trip ends once data sent after the round started has been delivered. Acks only
+        // carry send times, so the start of the round is marked by time rather than by the
+        // delivered byte count, which is equivalent and immune to packet number reordering.
+        if self
+            .current_round_trip_start
+            .map_or(true, |start| sent >= start)
+        {
+            self.is_round_trip_end_acked = true;
+        }
+        self.latest_rtt = rtt.latest();
+        self.smoothed_rtt = rtt.get();
+        // Track the minimum over the samples accepted here rather than the estimator's lifetime
+        // minimum, so that a rejected outlier can't pin it. Before the first RTT sample the latest
+        // RTT may still be zero; prefer the connection's current RTT estimate rather than
+        // latching `min_rtt` to zero.
+        let sample = match rtt.latest() {
+            latest if latest.is_zero() => rtt.get(),
+            latest => latest,
+        };
+        if !sample.is_zero()
+            && !self.is_rtt_outlier(sample)
+            && (!self.min_rtt_valid
+                || self.is_min_rtt_expired(now, app_limited)
+                || self.min_rtt > sample)
+        {
+            self.min_rtt = sample;
+            self.min_rtt_valid = true;
         }
     }
 
+    fn on_ack_range(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        count: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        if count == 0 {
+            return;
+        }
+        self.on_ack(now, sent, bytes, app_limited, rtt);
+        // The AIMD window grows with every acknowledged packet, so it needs to see the whole range.
+        if let Some(aimd) = &mut self.aimd {
+            aimd.on_ack_range(now, sent, bytes, count - 1, app_limited, rtt);
+        }
+        // The remaining packets were acknowledged at the same time, so beyond their byte count
+        // they carry no information that the first one didn't.
+        let remaining = bytes * (count - 1);
+        self.max_bandwidth.on_simultaneous_ack(remaining);
+        self.acked_bytes += remaining;
+    }
+
     fn on_end_acks(
         &mut self,
         now: Instant,
@@ -415,32 +1057,75 @@ impl Controller for Bbr {
         app_limited: bool,
         largest_packet_num_acked: Option<u64>,
     ) {
+        if let Some(updated_at) = self.mode_durations_updated_at {
+            *self.mode_durations.get_mut(self.mode) += now.saturating_duration_since(updated_at);
+        }
+        self.mode_durations_updated_at = Some(now);
+        self.record_delivery(now);
+
+        let Some(largest_acked_packet) = largest_packet_num_acked else {
+            // Nothing has been acknowledged yet, so there's no feedback to update the model with.
+            // Any losses are accounted for with the first acknowledgement.
+            return;
+        };
+
         let bytes_acked = self.max_bandwidth.bytes_acked_this_window();
         let excess_acked = self.ack_aggregation.update_ack_aggregation_bytes(
             bytes_acked,
             now,
             self.round_count,
-            self.max_bandwidth.get_estimate(),
+            self.max_bandwidth.get_estimate().as_bytes_per_sec(),
         );
         self.max_bandwidth.end_acks(self.round_count, app_limited);
-        if let Some(largest_acked_packet) = largest_packet_num_acked {
-            self.max_acked_packet_number = largest_acked_packet;
-        }
-
-        let mut is_round_start = false;
-        if bytes_acked > 0 {
-            is_round_start =
-                self.max_acked_packet_number > self.current_round_trip_end_packet_number;
-            if is_round_start {
-                self.current_round_trip_end_packet_number = self.max_sent_packet_number;
-                self.round_count += 1;
+        self.max_acked_packet_number = largest_acked_packet;
+
+        let is_round_start = bytes_acked > 0 && self.is_round_trip_end_acked;
+        self.loss_state.on_end_acks(bytes_acked);
+        if is_round_start {
+            self.current_round_trip_start = Some(now);
+            self.is_round_trip_end_acked = false;
+            self.round_count += 1;
+            self.round_start_cwnd = self.cwnd;
+            if self.mode == BbrMode::Drain {
+                self.drain_round_count += 1;
             }
+            let loss_rate = self.loss_state.end_round();
+            if loss_rate >= K_POLICER_LOSS_THRESHOLD {
+                self.lossy_round_count += 1;
+            } else {
+                self.lossy_round_count = 0;
+            }
+            // Probing has overshot the path's capacity, so start draining the excess right away
+            if self.mode == BbrMode::ProbeBw
+                && self.pacing_gain > 1.0
+                && loss_rate as f32 > self.config.loss_threshold
+            {
+                self.advance_gain_cycle_phase();
+            }
+            // Growing any further would only deepen the losses, so treat the bandwidth as found
+            if self.mode == BbrMode::Startup
+                && loss_rate as f32 > self.config.loss_threshold
+                && self.config.startup_loss_exit.is_some_and(|min| {
+                    let bw = self.max_bandwidth.get_estimate();
+                    bw.as_bytes_per_sec() > 0 && bw >= min
+                })
+            {
+                self.is_at_full_bandwidth = true;
+            }
+            if self.round_max_delivery_rate > 0 {
+                self.full_sample_rounds =
+                    (self.full_sample_rounds + 1).min(self.config.bandwidth_window_rounds);
+            }
+            if self.config.fast_convergence && self.mode == BbrMode::ProbeBw {
+                self.check_bandwidth_drop();
+            }
+            self.round_max_delivery_rate = 0;
         }
 
-        self.update_recovery_state(is_round_start);
+        self.update_recovery_state(now, is_round_start);
 
-        if self.mode == Mode::ProbeBw {
-            self.update_gain_cycle_phase(now, in_flight);
+        if self.mode == BbrMode::ProbeBw {
+            self.update_gain_cycle_phase(in_flight);
         }
 
         if is_round_start && !self.is_at_full_bandwidth {
@@ -455,51 +1140,175 @@ impl Controller for Bbr {
         self.calculate_pacing_rate();
         self.calculate_cwnd(bytes_acked, excess_acked);
         self.calculate_recovery_window(bytes_acked, self.loss_state.lost_bytes, in_flight);
+        if is_round_start {
+            self.update_stall_state(in_flight);
+        }
 
         self.prev_in_flight_count = in_flight;
         self.loss_state.reset();
+        self.debug_check_invariants();
     }
 
     fn on_congestion_event(
         &mut self,
-        _now: Instant,
-        _sent: Instant,
-        _is_persistent_congestion: bool,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
         lost_bytes: u64,
     ) {
+        if let Some(aimd) = &mut self.aimd {
+            aimd.on_congestion_event(now, sent, is_persistent_congestion, lost_bytes);
+        }
         self.loss_state.lost_bytes += lost_bytes;
+        self.lost_bytes += lost_bytes;
+        // ECN congestion experienced marks arrive as events without any lost bytes
+        if lost_bytes == 0 {
+            return;
+        }
+        if let Some(hook) = &self.config.on_loss {
+            (hook.0)(self.loss_state.rate());
+        }
+    }
+
+    fn on_idle(&mut self, idle_duration: Duration) {
+        // The queue drained while idle, so there's no need to drain it upon resuming.
+        self.exiting_quiescence = true;
+        if idle_duration < self.config.probe_rtt_interval {
+            // Resume at the estimated bandwidth rather than probing above it right away.
+            if self.mode == BbrMode::ProbeBw && self.pacing_gain > 1.0 {
+                self.pacing_gain = 1.0;
+                self.calculate_pacing_rate();
+            }
+            return;
+        }
+
+        // The path model is stale. Restart from the initial window, like a new connection would,
+        // and rediscover the bandwidth in STARTUP.
+        self.max_bandwidth.reset_estimate(self.round_count, 0);
+        self.full_sample_rounds = 0;
+        self.cwnd = self.cwnd.min(self.init_cwnd);
+        if let Some(rate) = BandwidthEstimation::bw_from_delta(self.cwnd, self.min_rtt) {
+            self.pacing_rate = rate.max(self.config.min_pacing_rate);
+        }
+        self.restart_startup();
+    }
+
+    fn on_path_change(&mut self) {
+        // The bottleneck may have moved. Keep the current estimates as a starting point, but
+        // probe for more bandwidth again.
+        self.restart_startup();
     }
 
     fn on_mtu_update(&mut self, new_mtu: u16) {
-        self.current_mtu = new_mtu as u64;
+        if let Some(aimd) = &mut self.aimd {
+            aimd.on_mtu_update(new_mtu);
+        }
+        let new_mtu = new_mtu as u64;
+        if new_mtu < self.current_mtu {
+            // Keep the windows' size in packets rather than in bytes, so that a smaller MTU (e.g.
+            // after detecting a PMTU black hole) doesn't allow more packets in flight.
+            self.cwnd = self.cwnd * new_mtu / self.current_mtu;
+            self.recovery_window = self.recovery_window * new_mtu / self.current_mtu;
+        }
+        self.current_mtu = new_mtu;
         self.min_cwnd = calculate_min_window(self.current_mtu);
-        self.init_cwnd = self.config.initial_window.max(self.min_cwnd);
-        self.cwnd = self.cwnd.max(self.min_cwnd);
+        self.init_cwnd = self.init_cwnd.max(self.min_cwnd);
+        self.cwnd = self.cwnd.max(self.min_cwnd).min(self.config.max_window);
+        if self.recovery_window != 0 {
+            self.recovery_window = self.recovery_window.max(self.min_cwnd);
+        }
     }
 
     fn window(&self) -> u64 {
-        if self.mode == Mode::ProbeRtt {
-            return self.get_probe_rtt_cwnd();
-        } else if self.recovery_state.in_recovery() && self.mode != Mode::Startup {
-            return self.cwnd.min(self.recovery_window);
+        #[cfg(test)]
+        if let Some(window) = self.window_override {
+            return window;
         }
-        self.cwnd
+        let window = if self.mode == BbrMode::ProbeRtt {
+            self.get_probe_rtt_cwnd()
+        } else if self.recovery_state.in_recovery() && self.mode != BbrMode::Startup {
+            self.cwnd.min(self.recovery_window)
+        } else {
+            self.cwnd
+        };
+        let window = match &self.aimd {
+            Some(aimd) => window.min(aimd.window()),
+            None => window,
+        };
+        window.min(self.config.max_window)
+    }
+
+    fn is_cwnd_limited(&self, in_flight: u64) -> bool {
+        // Pacing holds the bytes in flight near the inflight target even when the window would
+        // allow more, and a partial packet can't be sent.
+        in_flight + self.current_mtu > self.window().min(self.inflight_target())
+    }
+
+    fn bandwidth_estimate(&self) -> Option<u64> {
+        Some(self.max_bandwidth.get_estimate().as_bytes_per_sec())
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        Some(self.pacing_rate)
+    }
+
+    fn next_send_time(
+        &self,
+        now: Instant,
+        last_send: Instant,
+        bytes_pending: u64,
+    ) -> Option<Instant> {
+        if self.pacing_rate == 0 {
+            return None;
+        }
+        let delay = match self.config.pacing_mode {
+            PacingMode::ByteRate => Duration::from_nanos(
+                (u128::from(bytes_pending) * 1_000_000_000 / u128::from(self.pacing_rate))
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+            ),
+            PacingMode::PacketInterval => {
+                let packets = bytes_pending.div_ceil(self.current_mtu);
+                self.packet_interval()?
+                    .saturating_mul(packets.try_into().unwrap_or(u32::MAX))
+            }
+        };
+        Some(now.max(last_send + delay))
     }
 
     fn metrics(&self) -> ControllerMetrics {
+        let delivery_rate = self.max_bandwidth.last_sample();
         ControllerMetrics {
             congestion_window: self.window(),
             ssthresh: None,
-            pacing_rate: Some(self.pacing_rate * 8),
+            pacing_rate: Some(Bandwidth::from_bytes_per_sec(self.pacing_rate).as_bits_per_sec()),
+            delivery_rate: delivery_rate
+                .map(|sample| Bandwidth::from_bytes_per_sec(sample.rate).as_bits_per_sec()),
+            delivery_rate_app_limited: delivery_rate.is_some_and(|sample| sample.app_limited),
+            round_count: Some(self.round_count),
+            cycle_count: Some(self.cycle_count),
+            steady_state_fraction: Some(self.mode_durations.fraction(BbrMode::ProbeBw)),
+            probe_bw_phase: self.probe_bw_phase(),
+            queue_delay: self
+                .min_rtt_valid
+                .then(|| self.latest_rtt.saturating_sub(self.min_rtt)),
+            bdp: match self.max_bandwidth.get_estimate().as_bytes_per_sec() {
+                0 => None,
+                bw => Some(self.min_rtt.as_micros() as u64 * bw / 1_000_000),
+            },
         }
     }
 
+    fn name(&self) -> &'static str {
+        "bbr"
+    }
+
     fn clone_box(&self) -> Box<dyn Controller> {
         Box::new(self.clone())
     }
 
     fn initial_window(&self) -> u64 {
-        self.config.initial_window
+        self.config.effective_initial_window()
     }
 
     fn into_any(self: Box<Self>) -> Box<dyn Any> {
@@ -511,22 +1320,479 @@ impl Controller for Bbr {
 #[derive(Debug, Clone)]
 pub struct BbrConfig {
     initial_window: u64,
+    probe_rtt_interval: Duration,
+    recovery_beta: f32,
+    on_mode_change: Option<Hook<dyn Fn(BbrMode) + Send + Sync>>,
+    on_loss: Option<Hook<dyn Fn(f64) + Send + Sync>>,
+    prior_state: Option<BbrState>,
+    fast_convergence: bool,
+    initial_rtt: Option<Duration>,
+    max_window: u64,
+    min_pacing_rate: u64,
+    max_startup_duration: Duration,
+    startup_growth_target: f32,
+    startup_full_bw_rounds: u64,
+    pacing_gain_cycle: Option<Vec<f32>>,
+    startup_pacing_gain: f32,
+    probe_bw_cwnd_gain: f32,
+    skip_startup: bool,
+    pacing_smoothing: Option<f32>,
+    loss_threshold: f32,
+    max_drain_rounds: u64,
+    conservative_probe_rtt: bool,
+    max_startup_growth: Option<f32>,
+    probe_rtt_cwnd_gain: f32,
+    startup_loss_exit: Option<Bandwidth>,
+    max_probe_pacing_gain: Option<f32>,
+    hybrid_cwnd: bool,
+    bandwidth_window_rounds: u64,
+    drain_rtt_tolerance: Option<f32>,
+    recovery_exit_rounds: u64,
+    min_rtt_floor: Option<f32>,
+    rate_sample_history: usize,
+    pacing_mode: PacingMode,
+    extra_acked_cap: f32,
+    goodput_history: Duration,
 }
 
 impl BbrConfig {
     /// Default limit on the amount of outstanding data in bytes.
     ///
     /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    ///
+    /// Clamped to [`max_window`](Self::max_window), regardless of the order in which they are set.
     pub fn initial_window(&mut self, value: u64) -> &mut Self {
         self.initial_window = value;
         self
     }
+
+    /// Maximum time between attempts to measure the path's minimum RTT by entering ProbeRTT.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn probe_rtt_interval(&mut self, value: Duration) -> &mut Self {
+        self.probe_rtt_interval = value;
+        self
+    }
+
+    /// Fraction of the current minimum RTT below which new RTT samples are ignored as outliers
+    ///
+    /// Guards against implausibly low samples, e.g. caused by a peer misreporting its ack delay,
+    /// pinning the minimum RTT and hence the BDP estimate too low. Must be finite and between 0.0
+    /// and 1.0. Defaults to `None`, i.e. every sample is accepted.
+    pub fn min_rtt_floor(&mut self, value: Option<f32>) -> Result<&mut Self, ConfigError> {
+        if value.is_some_and(|floor| !(0.0..=1.0).contains(&floor)) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.min_rtt_floor = value;
+        Ok(self)
+    }
+
+    /// Multiplicative decrease applied to the congestion window when entering recovery.
+    ///
+    /// Losses observed during recovery are subtracted from the resulting window in addition.
+    /// Must be finite, positive and at most 1.0. Defaults to 0.7.
+    pub fn recovery_beta(&mut self, value: f32) -> Result<&mut Self, ConfigError> {
+        if !(value.is_finite() && value > 0.0 && value <= 1.0) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.recovery_beta = value;
+        Ok(self)
+    }
+
+    /// Number of consecutive loss-free rounds after which recovery ends
+    ///
+    /// Requiring more than one confirms that the losses are over before the window is allowed to
+    /// grow again, which helps on links with bursty losses. Must be at least 1. Defaults to 1.
+    pub fn recovery_exit_rounds(&mut self, value: u64) -> Result<&mut Self, ConfigError> {
+        if value == 0 {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.recovery_exit_rounds = value;
+        Ok(self)
+    }
+
+    /// Callback invoked with the new mode whenever a controller changes mode
+    ///
+    /// Allows applications to react to e.g. leaving STARTUP or entering PROBE_RTT. The callback
+    /// runs synchronously while the controller processes acknowledgements, and is shared by every
+    /// controller built from this configuration, so it should return quickly. It only receives the
+    /// new mode and thus cannot re-enter the controller.
+    pub fn on_mode_change(
+        &mut self,
+        value: Option<Arc<dyn Fn(BbrMode) + Send + Sync>>,
+    ) -> &mut Self {
+        self.on_mode_change = value.map(Hook);
+        self
+    }
+
+    /// Callback invoked whenever packets are deemed lost, with the fraction of bytes lost so far in
+    /// the current round
+    ///
+    /// Not invoked for congestion signalled by ECN alone, which involves no loss. Allows applications to adapt to the loss rate, e.g. by adding more forward error
+    /// correction. Like [`on_mode_change`](Self::on_mode_change), the callback runs synchronously
+    /// and is shared by every controller built from this configuration.
+    pub fn on_loss(&mut self, value: Option<Arc<dyn Fn(f64) + Send + Sync>>) -> &mut Self {
+        self.on_loss = value.map(Hook);
+        self
+    }
+
+    /// Path model exported from a previous connection to the same peer
+    ///
+    /// Seeds the minimum RTT and bandwidth estimate, and sizes the initial window to the resulting
+    /// BDP instead of [`initial_window`](Self::initial_window). Useful for 0-RTT resumption.
+    pub fn prior_state(&mut self, value: Option<BbrState>) -> &mut Self {
+        self.prior_state = value;
+        self.skip_startup = false;
+        self
+    }
+
+    /// Start directly in PROBE_BW with the given path model, skipping STARTUP
+    ///
+    /// Saves the round trips STARTUP would spend discovering the bandwidth of a well-known path,
+    /// e.g. for short-lived connections to a well-characterized server. Overrides
+    /// [`prior_state`](Self::prior_state). If the seeded bandwidth is too high, the controller only
+    /// corrects it once the bandwidth filter expires or losses shrink the window, so this should
+    /// only be used for paths whose characteristics are known.
+    pub fn skip_startup(&mut self, initial_bandwidth: u64, initial_rtt: Duration) -> &mut Self {
+        self.prior_state = Some(BbrState {
+            min_rtt: initial_rtt,
+            bandwidth: initial_bandwidth,
+        });
+        self.skip_startup = true;
+        self
+    }
+
+    /// Whether to react to sharp, sustained drops in delivery rate within a few rounds
+    ///
+    /// Normally the bandwidth estimate only falls once the windowed max filter has expired its
+    /// higher samples. With fast convergence, the estimate is lowered to the recently measured
+    /// delivery rate once that has stayed well below the estimate for several rounds in PROBE_BW,
+    /// e.g. after a handover from Wi-Fi to a cellular network. Defaults to `false`.
+    pub fn fast_convergence(&mut self, value: bool) -> &mut Self {
+        self.fast_convergence = value;
+        self
+    }
+
+    /// Minimum RTT assumed until the path's RTT has been measured
+    ///
+    /// Applications with prior knowledge of the path can use this to derive a realistic BDP from
+    /// the earliest bandwidth samples. Defaults to `None`, in which case the BDP is unknown until
+    /// the first RTT sample and the initial window is used instead.
+    pub fn initial_rtt(&mut self, value: Option<Duration>) -> &mut Self {
+        self.initial_rtt = value;
+        self
+    }
+
+    /// Upper bound on the congestion window in bytes, e.g. to limit buffer usage
+    ///
+    /// Must be at least the minimum window for the smallest permitted MTU, i.e. 4 packets of 1200
+    /// bytes. Takes precedence over the minimum window of paths with larger MTUs. Defaults to
+    /// `u64::MAX`.
+    pub fn max_window(&mut self, value: u64) -> Result<&mut Self, ConfigError> {
+        if value < calculate_min_window(BASE_DATAGRAM_SIZE) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.max_window = value;
+        Ok(self)
+    }
+
+    /// Lower bound on the pacing rate in bytes per second
+    ///
+    /// Defaults to 0, so that senders on very slow links are never paced faster than the link.
+    pub fn min_pacing_rate(&mut self, value: u64) -> &mut Self {
+        self.min_pacing_rate = value;
+        self
+    }
+
+    /// Maximum time spent in STARTUP before moving on to DRAIN regardless of bandwidth growth
+    ///
+    /// Defaults to 3 seconds.
+    pub fn max_startup_duration(&mut self, value: Duration) -> &mut Self {
+        self.max_startup_duration = value;
+        self
+    }
+
+    /// Factor by which the bandwidth estimate must grow in a round for STARTUP to continue
+    ///
+    /// Must be finite and greater than 1.0. Defaults to 1.25.
+    pub fn startup_growth_target(&mut self, value: f32) -> Result<&mut Self, ConfigError> {
+        if !(value.is_finite() && value > 1.0) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.startup_growth_target = value;
+        Ok(self)
+    }
+
+    /// Number of rounds without the bandwidth estimate reaching the
+    /// [`startup_growth_target`](Self::startup_growth_target) after which STARTUP ends
+    ///
+    /// Must be at least 1. Defaults to 3.
+    pub fn startup_full_bw_rounds(&mut self, value: u64) -> Result<&mut Self, ConfigError> {
+        if value == 0 {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.startup_full_bw_rounds = value;
+        Ok(self)
+    }
+
+    /// Pacing gains cycled through in PROBE_BW, one phase per round trip
+    ///
+    /// The cycle must have between 2 and 256 phases of finite, positive gains, at least one of
+    /// which is above 1.0 to probe for more bandwidth; in particular, empty and single-phase cycles
+    /// are rejected. Defaults to `None`, i.e. `[1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]`.
+    pub fn pacing_gain_cycle(&mut self, value: Option<Vec<f32>>) -> Result<&mut Self, ConfigError> {
+        if let Some(cycle) = &value {
+            if !(2..=256).contains(&cycle.len())
+                || !cycle.iter().all(|gain| gain.is_finite() && *gain > 0.0)
+                || !cycle.iter().any(|&gain| gain > 1.0)
+            {
+                return Err(ConfigError::OutOfBounds);
+            }
+        }
+        self.pacing_gain_cycle = value;
+        Ok(self)
+    }
+
+    /// Pacing gain used in STARTUP to grow the sending rate each round trip
+    ///
+    /// DRAIN paces at the reciprocal of this gain to drain the queue built up in STARTUP. Must be
+    /// finite and greater than 1.0. Defaults to 2.885, i.e. `2 / ln(2)`.
+    pub fn startup_pacing_gain(&mut self, value: f32) -> Result<&mut Self, ConfigError> {
+        if !(value.is_finite() && value > 1.0) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.startup_pacing_gain = value;
+        Ok(self)
+    }
+
+    /// Congestion window gain applied to the estimated BDP in PROBE_BW
+    ///
+    /// Lower gains reduce queueing in steady state at the cost of throughput when acknowledgements
+    /// are delayed or aggregated. Must be finite and positive. Defaults to 2.0.
+    pub fn probe_bw_cwnd_gain(&mut self, value: f32) -> Result<&mut Self, ConfigError> {
+        if !(value.is_finite() && value > 0.0) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.probe_bw_cwnd_gain = value;
+        Ok(self)
+    }
+
+    /// Weight of each new target in an exponentially weighted moving average of the pacing rate
+    ///
+    /// Once STARTUP has found the path's bandwidth, the pacing rate moves this fraction of the way
+    /// towards its target on every acknowledgement rather than jumping whenever the bandwidth
+    /// estimate or pacing gain changes, which reduces send bursts. Must be in `(0, 1]`. Defaults
+    /// to `None`, i.e. no smoothing.
+    pub fn pacing_smoothing(&mut self, value: Option<f32>) -> Result<&mut Self, ConfigError> {
+        if value.is_some_and(|weight| !(weight > 0.0 && weight <= 1.0)) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.pacing_smoothing = value;
+        Ok(self)
+    }
+
+    /// Fraction of a round's bytes that may be lost before the round is considered lossy
+    ///
+    /// A lossy round during the bandwidth probing phase of PROBE_BW ends the probe early and moves
+    /// on to the next, draining, phase of the gain cycle, and one during STARTUP ends STARTUP (see
+    /// [`startup_loss_exit`](Self::startup_loss_exit)). Must be between 0.0 and 1.0. Defaults to
+    /// 0.02.
+    pub fn loss_threshold(&mut self, value: f32) -> Result<&mut Self, ConfigError> {
+        if !(0.0..=1.0).contains(&value) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.loss_threshold = value;
+        Ok(self)
+    }
+
+    /// Minimum bandwidth that must have been measured before a lossy round ends STARTUP
+    ///
+    /// A round losing more than [`loss_threshold`](Self::loss_threshold) of its bytes ends
+    /// STARTUP early, without waiting for the bandwidth estimate to plateau, once a non-zero
+    /// bandwidth of at least this much has been measured. `None` disables this exit. Defaults to
+    /// requiring any bandwidth sample.
+    pub fn startup_loss_exit(&mut self, value: Option<Bandwidth>) -> &mut Self {
+        self.startup_loss_exit = value;
+        self
+    }
+
+    /// Maximum number of rounds spent in DRAIN before moving on to PROBE_BW
+    ///
+    /// DRAIN normally ends once the bytes in flight fall to the estimated BDP, which competing
+    /// traffic may prevent. Defaults to 3.
+    pub fn max_drain_rounds(&mut self, value: u64) -> &mut Self {
+        self.max_drain_rounds = value;
+        self
+    }
+
+    /// End DRAIN once the smoothed RTT is within this fraction of the minimum RTT
+    ///
+    /// By default DRAIN ends once the bytes in flight fall to the estimated BDP, which relies on
+    /// the bandwidth estimate being accurate. Observing the queueing delay itself disappear is an
+    /// alternative that doesn't. [`max_drain_rounds`](Self::max_drain_rounds) applies either way.
+    /// Must be finite and non-negative. Defaults to `None`.
+    pub fn drain_rtt_tolerance(&mut self, value: Option<f32>) -> Result<&mut Self, ConfigError> {
+        if value.is_some_and(|tolerance| !(tolerance.is_finite() && tolerance >= 0.0)) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.drain_rtt_tolerance = value;
+        Ok(self)
+    }
+
+    /// Whether to drain the bytes in flight all the way to the minimum window in PROBE_RTT
+    ///
+    /// By default PROBE_RTT limits the window to three quarters of the estimated BDP, which
+    /// retains most of the throughput but may leave a standing queue on paths shared with other
+    /// flows, hiding the true minimum RTT. Defaults to `false`.
+    pub fn conservative_probe_rtt(&mut self, value: bool) -> &mut Self {
+        self.conservative_probe_rtt = value;
+        self
+    }
+
+    /// Maximum factor by which the congestion window may grow per round in STARTUP
+    ///
+    /// Without a limit the window roughly doubles every round, which can cause large bursts when
+    /// pacing is coarse. Must be finite and at least 1.0. Defaults to `None`, i.e. no limit.
+    pub fn max_startup_growth(&mut self, value: Option<f32>) -> Result<&mut Self, ConfigError> {
+        if value.is_some_and(|growth| !(growth.is_finite() && growth >= 1.0)) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.max_startup_growth = value;
+        Ok(self)
+    }
+
+    /// Congestion window gain applied to the estimated BDP in PROBE_RTT
+    ///
+    /// The window never falls below the minimum window, and gains below 1.0 are needed for the
+    /// queue to drain so that the minimum RTT can be measured. Ignored if
+    /// [`conservative_probe_rtt`](Self::conservative_probe_rtt) is enabled. Must be finite and
+    /// positive. Defaults to 0.75.
+    pub fn probe_rtt_cwnd_gain(&mut self, value: f32) -> Result<&mut Self, ConfigError> {
+        if !(value.is_finite() && value > 0.0) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.probe_rtt_cwnd_gain = value;
+        Ok(self)
+    }
+
+    /// Limit on the window allowance for ack aggregation, as a multiple of the estimated BDP
+    ///
+    /// Keeps pathological aggregation, e.g. acknowledgements held back for a long time by a
+    /// middlebox, from inflating the congestion window far beyond what the path holds. See
+    /// [`Bbr::extra_acked`]. Must be finite and non-negative. Defaults to 1.0.
+    pub fn extra_acked_cap(&mut self, value: f32) -> Result<&mut Self, ConfigError> {
+        if !(value.is_finite() && value >= 0.0) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.extra_acked_cap = value;
+        Ok(self)
+    }
+
+    /// Maximum pacing gain of the bandwidth probing phases of PROBE_BW
+    ///
+    /// Probing at 1.25 times the estimated bandwidth may overflow shallow buffers and cause
+    /// losses; a lower cap makes probing gentler, at the cost of discovering extra bandwidth more
+    /// slowly. Must be finite and at least 1.0. Defaults to `None`, i.e. no limit.
+    pub fn max_probe_pacing_gain(&mut self, value: Option<f32>) -> Result<&mut Self, ConfigError> {
+        if value.is_some_and(|gain| !(gain.is_finite() && gain >= 1.0)) {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.max_probe_pacing_gain = value;
+        Ok(self)
+    }
+
+    /// Whether to also limit the window with a loss-based AIMD window, like [`NewReno`]'s
+    ///
+    /// Sending is still paced according to the bandwidth model, but the window reacts to every
+    /// loss by backing off multiplicatively and only grows back additively, which is more
+    /// conservative towards competing loss-based flows. Defaults to `false`.
+    pub fn hybrid_cwnd(&mut self, value: bool) -> &mut Self {
+        self.hybrid_cwnd = value;
+        self
+    }
+
+    /// Number of rounds over which the maximum delivery rate is taken as the bandwidth estimate
+    ///
+    /// Samples older than this age out, so that the estimate follows sustained drops in bandwidth.
+    /// Longer windows ride out longer dips, e.g. during PROBE_RTT or application-limited periods.
+    /// Must be at least 1. Defaults to 10.
+    pub fn bandwidth_window_rounds(&mut self, value: u64) -> Result<&mut Self, ConfigError> {
+        if value == 0 {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.bandwidth_window_rounds = value;
+        Ok(self)
+    }
+
+    /// How the pacing rate is turned into send times by [`Controller::next_send_time`]
+    ///
+    /// Defaults to [`PacingMode::ByteRate`].
+    pub fn pacing_mode(&mut self, value: PacingMode) -> &mut Self {
+        self.pacing_mode = value;
+        self
+    }
+
+    /// Longest trailing window over which [`Bbr::goodput`] can be computed
+    ///
+    /// Deliveries are recorded at a granularity of one millisecond, so longer histories take up
+    /// proportionally more memory. Defaults to zero, which disables recording.
+    pub fn goodput_history(&mut self, value: Duration) -> &mut Self {
+        self.goodput_history = value;
+        self
+    }
+
+    /// Number of recent delivery rate samples retained for [`Bbr::take_samples`]
+    ///
+    /// Once full, the oldest sample is discarded for each new one. Defaults to 0, which disables
+    /// recording.
+    pub fn rate_sample_history(&mut self, value: usize) -> &mut Self {
+        self.rate_sample_history = value;
+        self
+    }
+
+    /// The initial window actually used, which may not exceed the maximum window
+    fn effective_initial_window(&self) -> u64 {
+        self.initial_window.min(self.max_window)
+    }
 }
 
 impl Default for BbrConfig {
     fn default() -> Self {
         Self {
             initial_window: K_MAX_INITIAL_CONGESTION_WINDOW * BASE_DATAGRAM_SIZE,
+            probe_rtt_interval: K_DEFAULT_PROBE_RTT_INTERVAL,
+            recovery_beta: K_DEFAULT_RECOVERY_BETA,
+            on_mode_change: None,
+            on_loss: None,
+            prior_state: None,
+            fast_convergence: false,
+            initial_rtt: None,
+            max_window: u64::MAX,
+            min_pacing_rate: 0,
+            max_startup_duration: K_DEFAULT_MAX_STARTUP_DURATION,
+            startup_growth_target: K_DEFAULT_STARTUP_GROWTH_TARGET,
+            startup_full_bw_rounds: K_DEFAULT_STARTUP_FULL_BW_ROUNDS,
+            pacing_gain_cycle: None,
+            startup_pacing_gain: K_STARTUP_PACING_GAIN,
+            probe_bw_cwnd_gain: K_PROBE_BW_CWND_GAIN,
+            skip_startup: false,
+            pacing_smoothing: None,
+            loss_threshold: K_DEFAULT_LOSS_THRESHOLD,
+            max_drain_rounds: K_DEFAULT_MAX_DRAIN_ROUNDS,
+            conservative_probe_rtt: false,
+            max_startup_growth: None,
+            probe_rtt_cwnd_gain: K_MODERATE_PROBE_RTT_MULTIPLIER,
+            startup_loss_exit: Some(Bandwidth::default()),
+            max_probe_pacing_gain: None,
+            hybrid_cwnd: false,
+            bandwidth_window_rounds: K_BANDWIDTH_WINDOW_ROUNDS,
+            drain_rtt_tolerance: None,
+            recovery_exit_rounds: K_DEFAULT_RECOVERY_EXIT_ROUNDS,
+            min_rtt_floor: None,
+            rate_sample_history: 0,
+            pacing_mode: PacingMode::ByteRate,
+            extra_acked_cap: K_DEFAULT_EXTRA_ACKED_CAP,
+            goodput_history: Duration::ZERO,
         }
     }
 }
@@ -537,6 +1803,111 @@ impl ControllerFactory for BbrConfig {
     }
 }
 
+/// Path model of a [`Bbr`] controller, exported for resumption by a later connection
+///
+/// Contains only plain values, so applications can persist it in whatever format they like.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BbrState {
+    /// Minimum RTT observed on the path
+    pub min_rtt: Duration,
+    /// Estimated bottleneck bandwidth in bytes per second
+    pub bandwidth: u64,
+}
+
+/// Time a [`Bbr`] controller spent in each [`BbrMode`]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ModeDurations {
+    /// Time spent in [`BbrMode::Startup`]
+    pub startup: Duration,
+    /// Time spent in [`BbrMode::Drain`]
+    pub drain: Duration,
+    /// Time spent in [`BbrMode::ProbeBw`]
+    pub probe_bw: Duration,
+    /// Time spent in [`BbrMode::ProbeRtt`]
+    pub probe_rtt: Duration,
+}
+
+impl ModeDurations {
+    /// Time spent in `mode`
+    pub fn get(&self, mode: BbrMode) -> Duration {
+        match mode {
+            BbrMode::Startup => self.startup,
+            BbrMode::Drain => self.drain,
+            BbrMode::ProbeBw => self.probe_bw,
+            BbrMode::ProbeRtt => self.probe_rtt,
+        }
+    }
+
+    /// Total time accounted for across all modes
+    pub fn total(&self) -> Duration {
+        self.startup + self.drain + self.probe_bw + self.probe_rtt
+    }
+
+    /// Fraction of the total time spent in `mode`, or 0 if no time has been accounted for yet
+    pub fn fraction(&self, mode: BbrMode) -> f64 {
+        let total = self.total();
+        match total.is_zero() {
+            true => 0.0,
+            false => self.get(mode).as_secs_f64() / total.as_secs_f64(),
+        }
+    }
+
+    fn get_mut(&mut self, mode: BbrMode) -> &mut Duration {
+        match mode {
+            BbrMode::Startup => &mut self.startup,
+            BbrMode::Drain => &mut self.drain,
+            BbrMode::ProbeBw => &mut self.probe_bw,
+            BbrMode::ProbeRtt => &mut self.probe_rtt,
+        }
+    }
+}
+
+/// An application-provided callback, opaque to `Debug`
+struct Hook<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> Clone for Hook<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+/// Static tuning used by a [`Bbr`] controller
+///
+/// Unlike the controller's state, these values don't change over the lifetime of a connection.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct BbrParameters {
+    /// Pacing gain used during STARTUP
+    pub high_gain: f32,
+    /// Pacing gains cycled through while in PROBE_BW, after applying
+    /// [`BbrConfig::max_probe_pacing_gain`]
+    pub pacing_gain_cycle: Vec<f32>,
+    /// Minimum congestion window, in multiples of the current MTU
+    pub min_window_packets: u64,
+    /// Maximum time between attempts to measure the path's minimum RTT
+    pub probe_rtt_interval: Duration,
+}
+
+/// Consecutive rounds whose delivery rate fell well below the bandwidth estimate
+#[derive(Debug, Default, Copy, Clone)]
+struct BandwidthDropState {
+    rounds: u64,
+    max_delivery_rate: u64,
+}
+
+/// Pacing budget accounting for explicitly marked bursts of sends
+#[derive(Debug, Default, Copy, Clone)]
+struct BurstState {
+    budget: u64,
+    updated_at: Option<Instant>,
+}
+
 #[derive(Debug, Default, Copy, Clone)]
 struct AckAggregationState {
     max_ack_height: MinMax,
@@ -578,20 +1949,67 @@ impl AckAggregationState {
     }
 }
 
+/// Operating mode of a [`Bbr`] controller
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-enum Mode {
-    // Startup phase of the connection.
+pub enum BbrMode {
+    /// Startup phase of the connection.
     Startup,
-    // After achieving the highest possible bandwidth during the startup, lower
-    // the pacing rate in order to drain the queue.
+    /// After achieving the highest possible bandwidth during the startup, lower
+    /// the pacing rate in order to drain the queue.
     Drain,
-    // Cruising mode.
+    /// Cruising mode.
     ProbeBw,
-    // Temporarily slow down sending in order to empty the buffer and measure
-    // the real minimum RTT.
+    /// Temporarily slow down sending in order to empty the buffer and measure
+    /// the real minimum RTT.
     ProbeRtt,
 }
 
+/// Gains applied by a [`Bbr`] controller in its current mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Gains {
+    /// Factor applied to the bandwidth estimate to obtain the pacing rate
+    pub pacing: f32,
+    /// Factor applied to the estimated BDP to obtain the target congestion window
+    pub cwnd: f32,
+}
+
+/// How a [`Bbr`] controller spaces out transmissions at its pacing rate
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PacingMode {
+    /// Delay each send in proportion to its size in bytes
+    ByteRate,
+    /// Space sends by a whole number of packet intervals, i.e. the time to send one full-sized
+    /// packet at the pacing rate
+    ///
+    /// Avoids the rounding error of per-byte delays on links with small MTUs.
+    PacketInterval,
+}
+
+/// A delivery rate measurement recorded by a [`Bbr`] controller
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RateSample {
+    /// Time at which the acknowledgement yielding the sample was processed
+    pub time: Instant,
+    /// Measured delivery rate
+    pub delivery_rate: Bandwidth,
+    /// Whether the sender was application-limited, making the sample a lower bound
+    pub app_limited: bool,
+}
+
+/// Phase of the gain cycle of a [`Bbr`] controller in [`BbrMode::ProbeBw`]
+///
+/// Phases are classified by their pacing gain relative to 1, as BBRv1's cycle consists of one
+/// probing phase, one draining phase and otherwise phases of unity gain.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ProbeBwPhase {
+    /// Sending faster than the estimated bandwidth to discover whether more is available
+    Up,
+    /// Sending slower than the estimated bandwidth to drain the queue built while probing
+    Down,
+    /// Sending at the estimated bandwidth
+    Unity,
+}
+
 // Indicates how the congestion control limits the amount of bytes in flight.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum RecoveryState {
@@ -613,6 +2031,9 @@ impl RecoveryState {
 #[derive(Debug, Clone, Default)]
 struct LossState {
     lost_bytes: u64,
+    // Bytes lost and acknowledged since the current round trip started.
+    round_lost_bytes: u64,
+    round_acked_bytes: u64,
 }
 
 impl LossState {
@@ -620,24 +2041,120 @@ impl LossState {
         self.lost_bytes = 0;
     }
 
+    pub(super) fn on_end_acks(&mut self, bytes_acked: u64) {
+        self.round_acked_bytes += bytes_acked;
+        self.round_lost_bytes += self.lost_bytes;
+    }
+
+    /// Fraction of bytes lost so far in the current round, including the current batch of losses
+    pub(super) fn rate(&self) -> f64 {
+        let lost = self.round_lost_bytes + self.lost_bytes;
+        match lost + self.round_acked_bytes {
+            0 => 0.0,
+            total => lost as f64 / total as f64,
+        }
+    }
+
+    /// Returns the fraction of bytes lost during the round that just ended and starts a new one
+    pub(super) fn end_round(&mut self) -> f64 {
+        let total = self.round_lost_bytes + self.round_acked_bytes;
+        let loss_rate = match total {
+            0 => 0.0,
+            _ => self.round_lost_bytes as f64 / total as f64,
+        };
+        self.round_lost_bytes = 0;
+        self.round_acked_bytes = 0;
+        loss_rate
+    }
+
     pub(super) fn has_losses(&self) -> bool {
         self.lost_bytes != 0
     }
 }
 
 fn calculate_min_window(current_mtu: u64) -> u64 {
-    4 * current_mtu
+    K_MIN_WINDOW_PACKETS * current_mtu
+}
+
+/// Multiplies `value` by `gain`, rounding down
+///
+/// With the `fixed-point` feature the gain is quantized to a Q16 fraction and the multiplication
+/// carried out in integer arithmetic, so that large values don't lose precision to an `f64`
+/// mantissa and results don't depend on floating point rounding. Gains are still stored, and
+/// converted, as `f32`, so this does not make the controller usable without an FPU.
+fn apply_gain(value: u64, gain: f32) -> u64 {
+    match cfg!(feature = "fixed-point") {
+        true => apply_gain_fixed(value, gain),
+        false => apply_gain_float(value, gain),
+    }
+}
+
+fn apply_gain_float(value: u64, gain: f32) -> u64 {
+    (value as f64 * gain as f64) as u64
+}
+
+/// Represents `gain` as a Q16 fraction, so that only the conversion involves floating point
+fn apply_gain_fixed(value: u64, gain: f32) -> u64 {
+    let gain = (gain * (1 << K_GAIN_FRACTION_BITS) as f32) as u128;
+    let scaled = (value as u128 * gain) >> K_GAIN_FRACTION_BITS;
+    scaled.try_into().unwrap_or(u64::MAX)
 }
 
-// The gain used for the STARTUP, equal to 2/ln(2).
-const K_DEFAULT_HIGH_GAIN: f32 = 2.885;
-// The newly derived CWND gain for STARTUP, 2.
-const K_DERIVED_HIGH_CWNDGAIN: f32 = 2.0;
+// The pacing gain used for STARTUP, equal to 2/ln(2): the smallest gain which allows the sending
+// rate to double each round trip.
+const K_STARTUP_PACING_GAIN: f32 = 2.885;
+// The CWND gain used for STARTUP and DRAIN. BBRv1 uses the same 2/ln(2) as for pacing.
+const K_STARTUP_CWND_GAIN: f32 = 2.885;
+// The CWND gain used for PROBE_BW, leaving room for delayed and stretched acks.
+const K_PROBE_BW_CWND_GAIN: f32 = 2.0;
+// Number of rounds over which the bandwidth estimate is the maximum delivery rate.
+const K_BANDWIDTH_WINDOW_ROUNDS: u64 = 10;
 // The cycle of gains used during the ProbeBw stage.
 const K_PACING_GAIN: [f32; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
 
-const K_STARTUP_GROWTH_TARGET: f32 = 1.25;
-const K_ROUND_TRIPS_WITHOUT_GROWTH_BEFORE_EXITING_STARTUP: u8 = 3;
+const K_DEFAULT_STARTUP_GROWTH_TARGET: f32 = 1.25;
+const K_DEFAULT_MAX_STARTUP_DURATION: Duration = Duration::from_secs(3);
+const K_DEFAULT_STARTUP_FULL_BW_ROUNDS: u64 = 3;
+
+// The minimum congestion window, in packets.
+const K_MIN_WINDOW_PACKETS: u64 = 4;
+
+// How often to enter ProbeRtt if the minimum RTT hasn't been refreshed in the meantime.
+const K_DEFAULT_PROBE_RTT_INTERVAL: Duration = Duration::from_secs(10);
+
+// Fraction of the congestion window retained when entering recovery.
+const K_DEFAULT_RECOVERY_BETA: f32 = 0.7;
+// Default number of loss-free rounds ending recovery.
+const K_DEFAULT_RECOVERY_EXIT_ROUNDS: u64 = 1;
+// Default limit on the ack aggregation allowance, as a multiple of the BDP.
+const K_DEFAULT_EXTRA_ACKED_CAP: f32 = 1.0;
+// Acknowledgement batches closer together than this share an entry in the goodput history.
+const K_GOODPUT_GRANULARITY: Duration = Duration::from_millis(1);
+
+// Rounds losing at least this fraction of their data are considered to be policed.
+const K_POLICER_LOSS_THRESHOLD: f64 = 0.2;
+// Default window gain in PROBE_RTT, reducing the bytes in flight enough to drain the queue while
+// retaining most of the throughput.
+const K_MODERATE_PROBE_RTT_MULTIPLIER: f32 = 0.75;
+// Default number of rounds after which DRAIN ends even if the queue hasn't drained.
+const K_DEFAULT_MAX_DRAIN_ROUNDS: u64 = 3;
+// Default fraction of bytes lost in a round above which bandwidth probing stops.
+const K_DEFAULT_LOSS_THRESHOLD: f32 = 0.02;
+// Consecutive policed rounds after which a traffic policer is suspected.
+const K_POLICER_DETECTION_ROUNDS: u64 = 4;
+
+// Consecutive rounds with the window below the bytes in flight before considering the sender
+// stalled, and before releasing a packet regardless.
+const K_STALLED_ROUNDS: u64 = 2;
+const K_MAX_STALLED_ROUNDS: u64 = 4;
+
+// With fast convergence, rounds delivering less than this fraction of the bandwidth estimate
+// indicate a drop in bandwidth, which is acted upon after that many consecutive rounds.
+const K_BANDWIDTH_DROP_RATIO: f64 = 0.75;
+const K_BANDWIDTH_DROP_ROUNDS: u64 = 3;
+
+// Number of fractional bits in the fixed-point representation of gains.
+const K_GAIN_FRACTION_BITS: u32 = 16;
 
 // Do not allow initial congestion window to be greater than 200 packets.
 const K_MAX_INITIAL_CONGESTION_WINDOW: u64 = 200;
@@ -0,0 +1,2152 @@
+use super::*;
+
+const MTU: u16 = 1200;
+
+fn bbr() -> Bbr {
+    Bbr::new(Arc::new(BbrConfig::default()), MTU)
+}
+
+/// A lossless path with a fixed bottleneck bandwidth and round-trip time
+struct Path {
+    now: Instant,
+    next_packet_number: u64,
+    rtt: RttEstimator,
+    min_rtt: Duration,
+    /// Bottleneck bandwidth in bytes per second
+    bandwidth: u64,
+    /// Whether the sender is application-limited
+    app_limited: bool,
+    /// Whether the packets of each round are acknowledged in reverse order
+    reordered: bool,
+    /// Number of packets lost at the start of each round
+    lost_packets: u64,
+    /// Packets are acknowledged in groups of this size, when the last of the group arrives
+    ack_aggregation: usize,
+    largest_acked: Option<u64>,
+}
+
+impl Path {
+    fn new(bandwidth: u64, min_rtt: Duration) -> Self {
+        Self {
+            now: Instant::now(),
+            next_packet_number: 0,
+            rtt: RttEstimator::new(min_rtt),
+            min_rtt,
+            bandwidth,
+            app_limited: false,
+            reordered: false,
+            lost_packets: 0,
+            ack_aggregation: 1,
+            largest_acked: None,
+        }
+    }
+
+    /// Sends `packets` full-sized packets spaced at the bottleneck rate, then acknowledges each
+    /// one a round trip after it was sent
+    fn round(&mut self, bbr: &mut Bbr, packets: u64) {
+        let bytes = MTU as u64;
+        let interval = Duration::from_nanos(bytes * 1_000_000_000 / self.bandwidth);
+        let sent = (0..packets)
+            .map(|i| {
+                let sent_at = self.now + interval * i as u32;
+                let packet_number = self.next_packet_number;
+                bbr.on_sent(sent_at, bytes, packet_number);
+                self.next_packet_number += 1;
+                (sent_at, packet_number)
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..sent.len() {
+            let group_end = ((i / self.ack_aggregation + 1) * self.ack_aggregation).min(sent.len());
+            let acked_at = sent[group_end - 1].0 + self.min_rtt;
+            let (sent_at, packet_number) = match self.reordered {
+                false => sent[i],
+                true => sent[sent.len() - i - 1],
+            };
+            let in_flight = (packets - i as u64 - 1) * bytes;
+            if (i as u64) < self.lost_packets {
+                bbr.on_congestion_event(acked_at, sent_at, false, bytes);
+                bbr.on_end_acks(acked_at, in_flight, self.app_limited, self.largest_acked);
+                continue;
+            }
+            self.rtt.update(Duration::ZERO, self.min_rtt);
+            bbr.on_ack(acked_at, sent_at, bytes, self.app_limited, &self.rtt);
+            self.largest_acked = self.largest_acked.max(Some(packet_number));
+            bbr.on_end_acks(acked_at, in_flight, self.app_limited, self.largest_acked);
+            self.now = acked_at;
+        }
+    }
+
+    /// Runs `rounds` round trips, each sending a full congestion window
+    fn run(&mut self, bbr: &mut Bbr, rounds: usize) {
+        for _ in 0..rounds {
+            let packets = (bbr.window() / MTU as u64).max(1);
+            self.round(bbr, packets);
+        }
+    }
+}
+
+#[test]
+fn app_limited_first_ack_seeds_min_rtt() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    let rtt = RttEstimator::new(Duration::from_millis(100));
+
+    bbr.on_sent(now, MTU as u64, 0);
+    bbr.on_ack(
+        now + Duration::from_millis(100),
+        now,
+        MTU as u64,
+        true,
+        &rtt,
+    );
+    assert_eq!(bbr.min_rtt, Duration::from_millis(100));
+}
+
+#[test]
+fn zero_min_rtt_falls_back_to_estimate() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    let mut rtt = RttEstimator::new(Duration::ZERO);
+    rtt.update(Duration::ZERO, Duration::from_millis(50));
+    rtt.update(Duration::ZERO, Duration::ZERO);
+    assert!(rtt.min().is_zero());
+    assert!(rtt.latest().is_zero());
+
+    bbr.on_sent(now, MTU as u64, 0);
+    bbr.on_ack(
+        now + Duration::from_millis(50),
+        now,
+        MTU as u64,
+        false,
+        &rtt,
+    );
+    assert!(!bbr.min_rtt.is_zero());
+    assert_eq!(bbr.min_rtt, rtt.get());
+}
+
+#[test]
+fn reports_delivery_rate_sample() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    let rtt = RttEstimator::new(Duration::from_millis(50));
+    let bytes = MTU as u64;
+
+    bbr.on_sent(now, bytes, 0);
+    bbr.on_sent(now + Duration::from_millis(1), bytes, 1);
+    assert_eq!(bbr.metrics().delivery_rate, None);
+
+    bbr.on_ack(now + Duration::from_millis(50), now, bytes, false, &rtt);
+    bbr.on_ack(
+        now + Duration::from_millis(52),
+        now + Duration::from_millis(1),
+        bytes,
+        false,
+        &rtt,
+    );
+    // Sent at 1200 bytes/ms but acked at 1200 bytes per 2ms, so the ack rate is the bottleneck
+    let metrics = bbr.metrics();
+    assert_eq!(metrics.delivery_rate, Some(600_000 * 8));
+    assert!(!metrics.delivery_rate_app_limited);
+
+    bbr.on_ack(
+        now + Duration::from_millis(54),
+        now + Duration::from_millis(1),
+        bytes,
+        true,
+        &rtt,
+    );
+    assert!(bbr.metrics().delivery_rate_app_limited);
+}
+
+#[test]
+fn parameters_reflect_config() {
+    let params = bbr().parameters();
+    assert_eq!(params.high_gain, K_STARTUP_PACING_GAIN);
+    assert_eq!(params.pacing_gain_cycle, K_PACING_GAIN);
+    assert_eq!(params.min_window_packets, 4);
+    assert_eq!(params.probe_rtt_interval, Duration::from_secs(10));
+
+    let mut config = BbrConfig::default();
+    config.probe_rtt_interval(Duration::from_secs(5));
+    let params = Bbr::new(Arc::new(config), MTU).parameters();
+    assert_eq!(params.probe_rtt_interval, Duration::from_secs(5));
+
+    // The reported cycle is the one actually paced at
+    let mut config = BbrConfig::default();
+    config.max_probe_pacing_gain(Some(1.1)).unwrap();
+    let params = Bbr::new(Arc::new(config), MTU).parameters();
+    assert_eq!(params.pacing_gain_cycle[0], 1.1);
+    assert_eq!(params.pacing_gain_cycle[1..], K_PACING_GAIN[1..]);
+}
+
+#[test]
+fn bandwidth_estimate() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+
+    let estimate = bbr.bandwidth_estimate().unwrap();
+    assert!(estimate > 0);
+    path.run(&mut bbr, 5);
+    assert_eq!(bbr.bandwidth_estimate(), Some(estimate));
+}
+
+#[test]
+fn app_limited_rounds_keep_bandwidth_model() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode, BbrMode::ProbeBw);
+    let estimate = bbr.max_bandwidth.get_estimate().as_bytes_per_sec();
+    let pacing_rate = bbr.pacing_rate;
+
+    // App-limited samples can only raise the max filter, so neither the estimate nor the derived
+    // pacing rate may decay while the application has nothing to send.
+    path.app_limited = true;
+    for _ in 0..20 {
+        path.round(&mut bbr, 2);
+        assert_eq!(
+            bbr.max_bandwidth.get_estimate().as_bytes_per_sec(),
+            estimate
+        );
+        assert!(bbr.pacing_rate >= pacing_rate * 3 / 4);
+    }
+}
+
+#[test]
+fn recommended_stream_window() {
+    let bbr = bbr();
+    let window = bbr.window();
+    assert_eq!(bbr.recommended_stream_window(0), window);
+    assert_eq!(bbr.recommended_stream_window(1), window);
+    assert_eq!(bbr.recommended_stream_window(4), window / 4);
+    assert_eq!(bbr.recommended_stream_window(u32::MAX), MTU as u64);
+}
+
+#[test]
+fn reordered_acks_count_true_round_trips() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.reordered = true;
+    for round in 1..=10 {
+        path.round(&mut bbr, 8);
+        assert_eq!(bbr.round_count, round);
+    }
+}
+
+#[test]
+fn burst_debits_pacing_budget() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    let capacity = bbr.window();
+    bbr.pacing_rate = 1_200_000;
+
+    bbr.begin_burst(now);
+    assert_eq!(bbr.pacing_budget(), capacity);
+    bbr.end_burst(now + Duration::from_millis(1), 6000);
+    assert_eq!(bbr.pacing_budget(), capacity - 6000);
+
+    // 5ms at 1.2 MB/s earns back exactly what the burst spent
+    bbr.begin_burst(now + Duration::from_millis(4));
+    assert_eq!(bbr.pacing_budget(), capacity - 2400);
+    bbr.end_burst(now + Duration::from_millis(6), 0);
+    assert_eq!(bbr.pacing_budget(), capacity);
+}
+
+#[test]
+fn recovery_window_applies_beta() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode, BbrMode::ProbeBw);
+
+    let now = path.now;
+    bbr.on_congestion_event(now, now, false, MTU as u64);
+    bbr.on_end_acks(now, bbr.cwnd, false, Some(path.next_packet_number - 1));
+    assert!(bbr.recovery_state.in_recovery());
+    assert!(bbr.recovery_window.abs_diff(bbr.cwnd * 7 / 10) <= 1);
+
+    for beta in [0.0, -0.5, 1.5, f32::NAN, f32::INFINITY] {
+        assert!(BbrConfig::default().recovery_beta(beta).is_err());
+    }
+    assert!(BbrConfig::default().recovery_beta(1.0).is_ok());
+}
+
+#[test]
+fn suggests_cubic_when_policed() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode, BbrMode::ProbeBw);
+    assert_eq!(bbr.suggest_switch(), None);
+
+    path.lost_packets = 4;
+    for _ in 0..K_POLICER_DETECTION_ROUNDS {
+        path.round(&mut bbr, 10);
+        assert_eq!(bbr.suggest_switch(), None);
+    }
+    path.round(&mut bbr, 10);
+    assert_eq!(bbr.suggest_switch(), Some("cubic"));
+
+    path.lost_packets = 0;
+    path.round(&mut bbr, 10);
+    path.round(&mut bbr, 10);
+    assert_eq!(bbr.suggest_switch(), None);
+}
+
+#[test]
+fn mode_change_callback() {
+    let observed = Arc::new(std::sync::Mutex::new(vec![]));
+    let mut config = BbrConfig::default();
+    config.on_mode_change(Some(Arc::new({
+        let observed = observed.clone();
+        move |mode| observed.lock().unwrap().push(mode)
+    })));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+
+    let mut sampled = vec![];
+    for _ in 0..30 {
+        let before = bbr.mode();
+        path.run(&mut bbr, 1);
+        if bbr.mode() != before {
+            sampled.push(bbr.mode());
+        }
+    }
+
+    let observed = observed.lock().unwrap();
+    assert!(observed.contains(&BbrMode::ProbeBw));
+    assert_eq!(observed.last(), Some(&bbr.mode()));
+    assert!(observed.windows(2).all(|pair| pair[0] != pair[1]));
+    // Every transition visible between rounds was reported, in order
+    let mut remaining = observed.iter();
+    assert!(sampled.iter().all(|mode| remaining.any(|x| x == mode)));
+}
+
+#[test]
+fn stall_detection_releases_window() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert!(!bbr.is_stalled());
+
+    // Far more data is in flight than the window allows, e.g. after a severe loss episode
+    let in_flight = 10 * bbr.window();
+    let mut now = path.now;
+    let mut stalled_rounds = 0;
+    for _ in 0..=K_MAX_STALLED_ROUNDS {
+        let sent = now;
+        now += Duration::from_millis(20);
+        bbr.on_ack(now, sent, MTU as u64, false, &path.rtt);
+        bbr.on_end_acks(now, in_flight, false, Some(path.next_packet_number - 1));
+        if bbr.is_stalled() {
+            stalled_rounds += 1;
+        }
+    }
+    assert_eq!(stalled_rounds, K_MAX_STALLED_ROUNDS - K_STALLED_ROUNDS + 1);
+    assert!(!bbr.is_stalled());
+    assert!(bbr.window() > in_flight);
+}
+
+#[test]
+fn startup_pacing_gain() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    let bytes = MTU as u64;
+    bbr.on_sent(now, bytes, 0);
+    bbr.on_sent(now + Duration::from_millis(1), bytes, 1);
+    bbr.max_bandwidth
+        .on_ack(now + Duration::from_millis(50), now, bytes, 0, false);
+    bbr.max_bandwidth.on_ack(
+        now + Duration::from_millis(52),
+        now + Duration::from_millis(1),
+        bytes,
+        0,
+        false,
+    );
+    assert_eq!(bbr.max_bandwidth.get_estimate().as_bytes_per_sec(), 600_000);
+
+    assert_eq!(bbr.mode(), BbrMode::Startup);
+    assert_eq!(bbr.cwnd_gain, 2.885);
+    bbr.pacing_rate = 1;
+    bbr.calculate_pacing_rate();
+    assert_eq!(bbr.pacing_rate, apply_gain(600_000, 2.885));
+}
+
+#[test]
+fn metrics_saturate_pacing_rate() {
+    let mut bbr = bbr();
+    bbr.pacing_rate = u64::MAX - 1;
+    assert_eq!(bbr.metrics().pacing_rate, Some(u64::MAX));
+}
+
+#[test]
+fn resumes_from_exported_state() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    let state = bbr.export_state().unwrap();
+    assert_eq!(state.min_rtt, Duration::from_millis(20));
+    assert_eq!(state.bandwidth, 1_200_000);
+
+    let mut config = BbrConfig::default();
+    config.prior_state(Some(state));
+    let resumed = Bbr::new(Arc::new(config), MTU);
+    assert_eq!(resumed.bandwidth().as_bytes_per_sec(), 1_200_000);
+    assert_eq!(resumed.window(), 24_000);
+    // The seeded estimate isn't passed on until the new connection has measured it
+    assert_eq!(resumed.export_state(), None);
+}
+
+#[test]
+fn mtu_decrease_rescales_windows() {
+    let mut bbr = Bbr::new(Arc::new(BbrConfig::default()), 1500);
+    let packets = bbr.cwnd / 1500;
+    bbr.recovery_window = 30_000;
+
+    bbr.on_mtu_update(1200);
+    assert_eq!(bbr.cwnd, packets * 1200);
+    assert_eq!(bbr.recovery_window, 24_000);
+    assert_eq!(bbr.min_cwnd, 4 * 1200);
+
+    // Windows already at the floor stay there
+    bbr.cwnd = bbr.min_cwnd;
+    bbr.on_mtu_update(1000);
+    assert_eq!(bbr.cwnd, 4 * 1000);
+    assert_eq!(bbr.min_cwnd, 4 * 1000);
+}
+
+#[test]
+fn metrics_report_round_and_cycle_counts() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    assert_eq!(bbr.metrics().round_count, Some(0));
+    assert_eq!(bbr.metrics().cycle_count, Some(0));
+
+    for round in 1..=5 {
+        path.round(&mut bbr, 10);
+        assert_eq!(bbr.metrics().round_count, Some(round));
+    }
+    path.run(&mut bbr, 20);
+    assert!(bbr.metrics().cycle_count.unwrap() > 0);
+}
+
+/// Feeds `estimation` a pair of delivery rate samples at `rate` bytes per second
+fn feed_samples(estimation: &mut BandwidthEstimation, now: &mut Instant, rate: u64, round: u64) {
+    let bytes = MTU as u64;
+    let interval = Duration::from_nanos(bytes * 1_000_000_000 / rate);
+    for _ in 0..2 {
+        estimation.on_sent(*now, bytes);
+        estimation.on_ack(*now + interval, *now, bytes, round, false);
+        *now += interval;
+    }
+}
+
+#[test]
+fn zero_interval_acks_yield_no_sample() {
+    let mut estimation = BandwidthEstimation::default();
+    let mut now = Instant::now();
+    // The first acknowledgement has no interval to measure over
+    let mut first = BandwidthEstimation::default();
+    first.on_sent(now, MTU as u64);
+    first.on_sent(now, MTU as u64);
+    let ack = now + Duration::from_millis(1);
+    assert_eq!(first.on_ack(ack, now, MTU as u64, 1, false), None);
+    assert_eq!(first.last_sample(), None);
+
+    feed_samples(&mut estimation, &mut now, 1_000_000, 1);
+    let sample = estimation.last_sample();
+    assert_eq!(sample.unwrap().rate, 1_000_000);
+
+    // Acknowledged at the instant it was sent
+    estimation.on_sent(now, MTU as u64);
+    assert_eq!(estimation.on_ack(now, now, MTU as u64, 2, false), None);
+    assert_eq!(estimation.last_sample(), sample);
+    assert_eq!(estimation.get_estimate().as_bytes_per_sec(), 1_000_000);
+
+    // Acknowledged at the same instant as the previous acknowledgement
+    let later = now + Duration::from_millis(1);
+    estimation.on_sent(now, MTU as u64);
+    estimation.on_ack(later, now, MTU as u64, 2, false);
+    let sample = estimation.last_sample();
+    let estimate = estimation.get_estimate();
+    estimation.on_sent(now, MTU as u64);
+    assert_eq!(estimation.on_ack(later, now, MTU as u64, 2, false), None);
+    assert_eq!(estimation.last_sample(), sample);
+    assert_eq!(estimation.get_estimate(), estimate);
+}
+
+#[test]
+fn bandwidth_estimate_is_windowed_max() {
+    let mut estimation = BandwidthEstimation::default();
+    let mut now = Instant::now();
+    for round in 1..=3 {
+        feed_samples(&mut estimation, &mut now, 1_000_000, round);
+    }
+    assert_eq!(estimation.get_estimate().as_bytes_per_sec(), 1_000_000);
+
+    // A transient dip doesn't collapse the estimate
+    feed_samples(&mut estimation, &mut now, 500_000, 4);
+    assert_eq!(estimation.get_estimate().as_bytes_per_sec(), 1_000_000);
+    feed_samples(&mut estimation, &mut now, 1_000_000, 5);
+    assert_eq!(estimation.get_estimate().as_bytes_per_sec(), 1_000_000);
+
+    // A sustained drop does, once the high samples have left the window
+    for round in 6..=20 {
+        feed_samples(&mut estimation, &mut now, 500_000, round);
+    }
+    assert_eq!(estimation.get_estimate().as_bytes_per_sec(), 500_000);
+}
+
+#[test]
+fn fast_convergence_after_bandwidth_drop() {
+    let run = |fast_convergence| {
+        let mut config = BbrConfig::default();
+        config.fast_convergence(fast_convergence);
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let mut path = Path::new(1_200_000, Duration::from_millis(20));
+        path.run(&mut bbr, 10);
+        assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+
+        path.bandwidth = 600_000;
+        for _ in 0..6 {
+            path.round(&mut bbr, 10);
+        }
+        bbr
+    };
+
+    let bbr = run(true);
+    assert_eq!(bbr.max_bandwidth.get_estimate().as_bytes_per_sec(), 600_000);
+    assert!(bbr.cwnd <= 2 * 12_000 + bbr.ack_aggregation.max_ack_height.get());
+    assert_eq!(
+        run(false).max_bandwidth.get_estimate().as_bytes_per_sec(),
+        1_200_000
+    );
+}
+
+#[test]
+fn drain_exit_respects_min_window() {
+    let mut bbr = bbr();
+    // A BDP of a single byte, far below the minimum window
+    bbr.max_bandwidth.seed(1_000);
+    bbr.min_rtt = Duration::from_millis(1);
+    bbr.is_at_full_bandwidth = true;
+
+    bbr.maybe_exit_startup_or_drain(Instant::now(), bbr.min_cwnd + 1);
+    assert_eq!(bbr.mode(), BbrMode::Drain);
+    bbr.maybe_exit_startup_or_drain(Instant::now(), bbr.min_cwnd);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+}
+
+#[test]
+fn initial_rtt_seeds_bdp() {
+    let early_bdp = |initial_rtt| {
+        let mut config = BbrConfig::default();
+        config.initial_window(10 * MTU as u64);
+        config.initial_rtt(initial_rtt);
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        bbr.max_bandwidth.seed(1_000_000);
+        bbr.get_target_cwnd(1.0)
+    };
+
+    assert_eq!(early_bdp(None), 10 * MTU as u64);
+    assert_eq!(early_bdp(Some(Duration::from_millis(100))), 100_000);
+}
+
+#[test]
+fn window_clamped_to_max_window() {
+    let mut config = BbrConfig::default();
+    config.max_window(20_000).unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(125_000_000, Duration::from_millis(50));
+    assert!(bbr.window() <= 20_000);
+    for _ in 0..20 {
+        path.run(&mut bbr, 1);
+        assert!(bbr.window() <= 20_000);
+        assert!(bbr.cwnd <= 20_000);
+    }
+}
+
+#[test]
+fn min_pacing_rate() {
+    let pacing_rate = |min_pacing_rate| {
+        let mut config = BbrConfig::default();
+        config.min_pacing_rate(min_pacing_rate);
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        bbr.max_bandwidth.seed(10_000);
+        bbr.is_at_full_bandwidth = true;
+        bbr.pacing_gain = 1.0;
+        bbr.calculate_pacing_rate();
+        bbr.pacing_rate
+    };
+
+    // Slow links may be paced well below 100 packets per second
+    assert_eq!(pacing_rate(0), 10_000);
+    assert!(pacing_rate(0) < 100 * MTU as u64);
+    assert_eq!(pacing_rate(50_000), 50_000);
+}
+
+#[test]
+fn startup_times_out() {
+    let mut bbr = bbr();
+    let rtt = RttEstimator::new(Duration::from_millis(100));
+    let start = Instant::now();
+    let mut now = start;
+
+    // App-limited samples never let the bandwidth plateau
+    let mut packet_number = 0;
+    while bbr.mode() == BbrMode::Startup {
+        assert!(now - start < Duration::from_secs(4));
+        bbr.on_sent(now, MTU as u64, packet_number);
+        let sent = now;
+        now += Duration::from_millis(100);
+        bbr.on_ack(now, sent, MTU as u64, true, &rtt);
+        bbr.on_end_acks(now, 0, true, Some(packet_number));
+        packet_number += 1;
+    }
+    assert_eq!(
+        now - start,
+        Duration::from_secs(3) + Duration::from_millis(100)
+    );
+    assert!(bbr.is_at_full_bandwidth);
+}
+
+#[test]
+fn window_compensates_ack_aggregation() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert_eq!(bbr.extra_acked(), 0);
+
+    path.ack_aggregation = 10;
+    path.run(&mut bbr, 5);
+    assert!(bbr.extra_acked() > 0);
+    assert_eq!(
+        bbr.cwnd,
+        bbr.get_target_cwnd(bbr.cwnd_gain) + bbr.extra_acked()
+    );
+}
+
+#[test]
+fn ack_aggregation_compensation_capped() {
+    assert!(BbrConfig::default().extra_acked_cap(-1.0).is_err());
+    let aggregated = |cap| {
+        let mut config = BbrConfig::default();
+        config.extra_acked_cap(cap).unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let mut path = Path::new(1_200_000, Duration::from_millis(20));
+        path.run(&mut bbr, 10);
+        // Acknowledge whole rounds at once
+        path.ack_aggregation = usize::MAX;
+        path.run(&mut bbr, 5);
+        assert_eq!(
+            bbr.cwnd,
+            bbr.get_target_cwnd(bbr.cwnd_gain) + bbr.extra_acked()
+        );
+        assert!(bbr.ack_aggregation.max_ack_height.get() > bbr.get_target_cwnd(1.0));
+        bbr
+    };
+
+    let bbr = aggregated(1.0);
+    assert_eq!(bbr.extra_acked(), bbr.get_target_cwnd(1.0));
+    let bbr = aggregated(0.5);
+    assert_eq!(bbr.extra_acked(), bbr.get_target_cwnd(0.5));
+}
+
+#[test]
+fn restart_after_idle() {
+    let mut config = BbrConfig::default();
+    config.initial_window(10 * MTU as u64);
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    let pacing_rate = bbr.pacing_rate;
+
+    // A short pause keeps the model but stops probing
+    bbr.on_idle(Duration::from_millis(100));
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert!(bbr.pacing_gain <= 1.0);
+    assert!(bbr.pacing_rate <= pacing_rate);
+
+    bbr.on_idle(Duration::from_secs(30));
+    assert_eq!(bbr.mode(), BbrMode::Startup);
+    assert_eq!(bbr.window(), 10 * MTU as u64);
+    assert_eq!(bbr.pacing_rate, 600_000);
+    assert!(bbr.pacing_rate < pacing_rate);
+}
+
+#[test]
+fn custom_pacing_gain_cycle() {
+    let mut config = BbrConfig::default();
+    assert!(config.pacing_gain_cycle(Some(vec![1.25])).is_err());
+    assert!(config.pacing_gain_cycle(Some(vec![1.0, 0.75])).is_err());
+    assert!(config.pacing_gain_cycle(Some(vec![1.5, 0.0])).is_err());
+    config
+        .pacing_gain_cycle(Some(vec![1.5, 0.5, 1.0, 1.0]))
+        .unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    assert_eq!(bbr.parameters().pacing_gain_cycle, vec![1.5, 0.5, 1.0, 1.0]);
+
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    let mut gains = Vec::new();
+    for _ in 0..20 {
+        path.run(&mut bbr, 1);
+        assert!([1.5, 0.5, 1.0].contains(&bbr.pacing_gain));
+        gains.push(bbr.pacing_gain);
+    }
+    assert!(gains.contains(&1.5));
+    assert!(gains.contains(&1.0));
+}
+
+#[test]
+fn cycle_offset_wraps_at_configured_length() {
+    let mut config = BbrConfig::default();
+    config
+        .pacing_gain_cycle(Some(vec![1.5, 0.5, 1.0, 1.0]))
+        .unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    bbr.max_bandwidth.seed(1_000_000);
+    bbr.min_rtt = Duration::from_millis(10);
+    bbr.enter_probe_bandwidth_mode();
+    assert!(bbr.current_cycle_offset < 4);
+    let mut offsets = Vec::new();
+    for _ in 0..8 {
+        bbr.advance_gain_cycle_phase();
+        offsets.push(bbr.current_cycle_offset);
+    }
+    let start = offsets[0];
+    assert!(
+        offsets
+            .iter()
+            .enumerate()
+            .all(|(i, &offset)| offset == (start + i) % 4)
+    );
+
+    // The longest permitted cycle doesn't overflow the offset
+    let mut config = BbrConfig::default();
+    let mut cycle = vec![1.0; 256];
+    cycle[0] = 1.25;
+    config.pacing_gain_cycle(Some(cycle)).unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    bbr.current_cycle_offset = 255;
+    bbr.advance_gain_cycle_phase();
+    assert_eq!(bbr.current_cycle_offset, 0);
+    assert_eq!(bbr.pacing_gain, 1.25);
+    for _ in 0..100 {
+        bbr.enter_probe_bandwidth_mode();
+        assert!(bbr.current_cycle_offset < 256);
+    }
+}
+
+#[test]
+fn mode_durations_cover_elapsed_time() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    let start = path.now;
+    path.run(&mut bbr, 20);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+
+    let durations = bbr.mode_durations();
+    let modes = [
+        BbrMode::Startup,
+        BbrMode::Drain,
+        BbrMode::ProbeBw,
+        BbrMode::ProbeRtt,
+    ];
+    let visited = modes
+        .iter()
+        .filter(|&&mode| !durations.get(mode).is_zero())
+        .count();
+    assert!(visited >= 2);
+    assert!(!durations.probe_bw.is_zero());
+    // Time is accounted for from the first acknowledgement onwards
+    assert_eq!(durations.total(), path.now - start - path.min_rtt);
+
+    let fraction = bbr.metrics().steady_state_fraction.unwrap();
+    assert_eq!(fraction, durations.fraction(BbrMode::ProbeBw));
+    assert!(fraction > 0.0 && fraction < 1.0);
+}
+
+#[test]
+fn drain_gain_is_reciprocal_of_startup_gain() {
+    let mut config = BbrConfig::default();
+    assert!(config.startup_pacing_gain(1.0).is_err());
+    assert!(config.startup_pacing_gain(f32::INFINITY).is_err());
+    config.startup_pacing_gain(2.5).unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    assert_eq!(bbr.pacing_gain, 2.5);
+    assert_eq!(bbr.parameters().high_gain, 2.5);
+
+    bbr.max_bandwidth.seed(1_000_000);
+    bbr.min_rtt = Duration::from_millis(10);
+    bbr.is_at_full_bandwidth = true;
+    bbr.maybe_exit_startup_or_drain(Instant::now(), u64::MAX);
+    assert_eq!(bbr.mode(), BbrMode::Drain);
+    assert_eq!(bbr.pacing_gain, 1.0 / 2.5);
+}
+
+#[test]
+fn probe_rtt_deferred_during_recovery() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    bbr.enter_probe_bandwidth_mode();
+    bbr.probe_rtt_last_started_at = Some(now);
+    let now = now + K_DEFAULT_PROBE_RTT_INTERVAL + Duration::from_millis(1);
+
+    bbr.recovery_state = RecoveryState::Growth;
+    bbr.maybe_enter_or_exit_probe_rtt(now, true, 0, false);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+
+    bbr.recovery_state = RecoveryState::NotInRecovery;
+    bbr.maybe_enter_or_exit_probe_rtt(now, true, 0, false);
+    assert_eq!(bbr.mode(), BbrMode::ProbeRtt);
+}
+
+#[test]
+fn display_summarizes_state() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    let summary = bbr.to_string();
+    assert!(summary.starts_with("ProbeBw "));
+    assert!(summary.contains(&format!("cwnd={} ", bbr.window())));
+    assert!(summary.contains("min_rtt=20ms"));
+    assert!(!summary.contains('\n'));
+}
+
+#[test]
+fn probe_bw_cwnd_gain_sets_steady_state_window() {
+    let mut config = BbrConfig::default();
+    assert!(config.probe_bw_cwnd_gain(0.0).is_err());
+    config.probe_bw_cwnd_gain(1.5).unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 30);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+
+    let bdp = 1_200_000 * 20 / 1000;
+    assert_eq!(bbr.cwnd_gain, 1.5);
+    assert_eq!(bbr.cwnd, bdp * 3 / 2 + bbr.extra_acked());
+}
+
+#[test]
+fn pacing_rate_accessor_is_unscaled() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    let controller: &dyn Controller = &bbr;
+    assert_eq!(controller.pacing_rate(), Some(bbr.pacing_rate));
+    assert_ne!(bbr.pacing_rate, 0);
+    assert_eq!(controller.metrics().pacing_rate, Some(bbr.pacing_rate * 8));
+}
+
+#[test]
+fn tiny_acks_do_not_drift_window() {
+    let mut bbr = bbr();
+    // A BDP which isn't a whole number of bytes at the usual gains
+    bbr.max_bandwidth.seed(1_234_567);
+    bbr.min_rtt = Duration::from_micros(33_333);
+    bbr.is_at_full_bandwidth = true;
+    bbr.enter_probe_bandwidth_mode();
+    bbr.cwnd = bbr.min_cwnd;
+
+    for _ in 0..1_000_000 {
+        bbr.calculate_cwnd(1, 0);
+    }
+    let bdp = 1_234_567.0 * 0.033_333;
+    let target = bdp * bbr.cwnd_gain as f64 + bbr.extra_acked() as f64;
+    assert!((bbr.cwnd as f64 - target).abs() < MTU as f64);
+}
+
+#[test]
+fn skip_startup_starts_in_probe_bw() {
+    let mut config = BbrConfig::default();
+    config.skip_startup(1_200_000, Duration::from_millis(20));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let bdp = 1_200_000 * 20 / 1000;
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert_eq!(bbr.window(), bdp);
+
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.round(&mut bbr, 1);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    for _ in 0..10 {
+        path.run(&mut bbr, 1);
+        assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    }
+    let window = bbr.window() as f64;
+    assert!(window >= bdp as f64 && window <= 2.5 * bdp as f64);
+}
+
+#[test]
+fn bandwidth_conversions() {
+    let bandwidth = Bandwidth::from_bytes_per_sec(1_250_000);
+    assert_eq!(bandwidth.as_bytes_per_sec(), 1_250_000);
+    assert_eq!(bandwidth.as_bits_per_sec(), 10_000_000);
+    assert_eq!(
+        Bandwidth::from_bytes_per_sec(u64::MAX).as_bits_per_sec(),
+        u64::MAX
+    );
+
+    let mut bbr = bbr();
+    bbr.max_bandwidth.seed(1_250_000);
+    assert_eq!(bbr.bandwidth(), bandwidth);
+    assert_eq!(bbr.bandwidth_estimate(), Some(1_250_000));
+}
+
+#[test]
+fn pacing_smoothing_ramps_rate() {
+    let step = |smoothing| {
+        let mut config = BbrConfig::default();
+        config.pacing_smoothing(smoothing).unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        bbr.is_at_full_bandwidth = true;
+        bbr.pacing_gain = 1.0;
+        bbr.max_bandwidth.seed(1_000_000);
+        bbr.calculate_pacing_rate();
+        assert_eq!(bbr.pacing_rate, 1_000_000);
+
+        bbr.max_bandwidth.reset_estimate(1, 2_000_000);
+        (0..10)
+            .map(|_| {
+                bbr.calculate_pacing_rate();
+                bbr.pacing_rate
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert!(BbrConfig::default().pacing_smoothing(Some(0.0)).is_err());
+    assert!(BbrConfig::default().pacing_smoothing(Some(1.5)).is_err());
+    assert!(step(None).iter().all(|&rate| rate == 2_000_000));
+    let smoothed = step(Some(0.25));
+    assert_eq!(smoothed[0], 1_250_000);
+    assert!(smoothed.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(*smoothed.last().unwrap() < 2_000_000);
+    assert!(*smoothed.last().unwrap() > 1_900_000);
+}
+
+#[test]
+fn invariants_hold_in_edge_cases() {
+    // Invariants are checked by debug assertions at the end of every `on_end_acks`
+    let mut config = BbrConfig::default();
+    assert!(config.max_window(0).is_err());
+    assert!(config.max_window(2 * MTU as u64).is_err());
+    config.max_window(4 * MTU as u64).unwrap();
+    config.initial_window(MTU as u64);
+    let mut small = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(125_000_000, Duration::from_millis(50));
+    path.run(&mut small, 10);
+    path.lost_packets = 1;
+    path.run(&mut small, 5);
+    small.on_mtu_update(MTU / 2);
+    path.lost_packets = 0;
+    path.run(&mut small, 5);
+
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    path.lost_packets = 20;
+    path.run(&mut bbr, 3);
+    bbr.on_idle(Duration::from_secs(30));
+    path.lost_packets = 0;
+    path.app_limited = true;
+    path.run(&mut bbr, 3);
+    path.app_limited = false;
+    path.run(&mut bbr, 20);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+}
+
+#[test]
+fn ack_range_matches_per_packet_acks() {
+    for hybrid_cwnd in [false, true] {
+        let mut config = BbrConfig::default();
+        config.hybrid_cwnd(hybrid_cwnd);
+        let config = Arc::new(config);
+        ack_range_matches_per_packet_acks_with(
+            Bbr::new(config.clone(), MTU),
+            Bbr::new(config, MTU),
+        );
+    }
+}
+
+fn ack_range_matches_per_packet_acks_with(mut per_packet: Bbr, mut batched: Bbr) {
+    let rtt = RttEstimator::new(Duration::from_millis(20));
+    let mut now = Instant::now();
+    let mut packet_number = 0;
+    for round in 0..20u64 {
+        // Bursts of 10 packets, acknowledged together one round trip later
+        let sent = now;
+        let count = 10 + round;
+        for bbr in [&mut per_packet, &mut batched] {
+            for i in 0..count {
+                bbr.on_sent(sent, MTU as u64, packet_number + i);
+            }
+        }
+        packet_number += count;
+        now += Duration::from_millis(20);
+        for _ in 0..count {
+            per_packet.on_ack(now, sent, MTU as u64, false, &rtt);
+        }
+        batched.on_ack_range(now, sent, MTU as u64, count, false, &rtt);
+        for bbr in [&mut per_packet, &mut batched] {
+            bbr.on_end_acks(now, 0, false, Some(packet_number - 1));
+        }
+
+        assert_eq!(batched.bandwidth(), per_packet.bandwidth());
+        assert_eq!(batched.window(), per_packet.window());
+        assert_eq!(batched.mode(), per_packet.mode());
+        assert_eq!(batched.round_count, per_packet.round_count);
+        assert_eq!(
+            batched.aimd.as_ref().map(|aimd| aimd.window()),
+            per_packet.aimd.as_ref().map(|aimd| aimd.window())
+        );
+    }
+    assert_ne!(batched.bandwidth().as_bytes_per_sec(), 0);
+}
+
+#[test]
+fn first_sample_replaces_seeded_min_rtt() {
+    let mut config = BbrConfig::default();
+    config.initial_rtt(Some(Duration::from_millis(1)));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    assert_eq!(bbr.min_rtt, Duration::from_millis(1));
+
+    let now = Instant::now();
+    let rtt = RttEstimator::new(Duration::from_millis(50));
+    bbr.on_sent(now, MTU as u64, 0);
+    bbr.on_ack(
+        now + Duration::from_millis(50),
+        now,
+        MTU as u64,
+        false,
+        &rtt,
+    );
+    assert_eq!(bbr.min_rtt, Duration::from_millis(50));
+
+    // Later samples only lower it
+    let rtt = RttEstimator::new(Duration::from_millis(80));
+    bbr.on_ack(
+        now + Duration::from_millis(80),
+        now,
+        MTU as u64,
+        false,
+        &rtt,
+    );
+    assert_eq!(bbr.min_rtt, Duration::from_millis(50));
+}
+
+#[test]
+fn lossy_round_ends_bandwidth_probe() {
+    for threshold in [-0.1, 1.5, f32::NAN, f32::INFINITY] {
+        assert!(BbrConfig::default().loss_threshold(threshold).is_err());
+    }
+    let probe = |lost_bytes: u64| {
+        let mut bbr = bbr();
+        let now = Instant::now();
+        let rtt = RttEstimator::new(Duration::from_millis(20));
+        bbr.max_bandwidth.seed(1_200_000);
+        bbr.is_at_full_bandwidth = true;
+        bbr.enter_probe_bandwidth_mode();
+        bbr.current_cycle_offset = 0;
+        bbr.pacing_gain = 1.25;
+
+        bbr.on_sent(now, MTU as u64, 0);
+        bbr.on_ack(now + rtt.get(), now, MTU as u64, false, &rtt);
+        // 100 KB delivered or lost in total this round, including the packet acked above
+        bbr.loss_state.round_lost_bytes = lost_bytes;
+        bbr.loss_state.round_acked_bytes = 100_000 - lost_bytes - MTU as u64;
+        bbr.on_end_acks(now + rtt.get(), u64::MAX, false, Some(0));
+        bbr.pacing_gain
+    };
+
+    assert_eq!(probe(0), 1.25);
+    assert_eq!(probe(1_000), 1.25);
+    assert_eq!(probe(2_000), 1.25);
+    assert_eq!(probe(2_001), 0.75);
+    assert_eq!(probe(10_000), 0.75);
+}
+
+#[test]
+fn drain_ends_after_round_limit() {
+    let mut config = BbrConfig::default();
+    config.max_drain_rounds(5);
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let rtt = RttEstimator::new(Duration::from_millis(10));
+    let mut now = Instant::now();
+    bbr.max_bandwidth.seed(1_000_000);
+    bbr.min_rtt = rtt.get();
+    bbr.is_at_full_bandwidth = true;
+    // Far more than the 10 KB BDP stays in flight throughout
+    let in_flight = 100_000;
+    bbr.maybe_exit_startup_or_drain(now, in_flight);
+    assert_eq!(bbr.mode(), BbrMode::Drain);
+
+    for round in 1..=5 {
+        bbr.on_sent(now, MTU as u64, round);
+        let sent = now;
+        now += rtt.get();
+        bbr.on_ack(now, sent, MTU as u64, false, &rtt);
+        bbr.on_end_acks(now, in_flight, false, Some(round));
+        match round {
+            5 => assert_eq!(bbr.mode(), BbrMode::ProbeBw),
+            _ => assert_eq!(bbr.mode(), BbrMode::Drain),
+        }
+    }
+}
+
+#[test]
+fn gain_cycle_advances_once_per_round() {
+    let mut config = BbrConfig::default();
+    config
+        .pacing_gain_cycle(Some(vec![1.25, 1.0, 1.0, 1.0]))
+        .unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+
+    // Rounds now take several times the minimum RTT
+    bbr.min_rtt = Duration::from_millis(5);
+    path.run(&mut bbr, 1);
+    for _ in 0..8 {
+        let cycle_count = bbr.cycle_count;
+        path.run(&mut bbr, 1);
+        assert_eq!(bbr.cycle_count, cycle_count + 1);
+    }
+}
+
+#[test]
+fn conservative_probe_rtt_drains_to_min_window() {
+    let probe_rtt = |conservative| {
+        let mut config = BbrConfig::default();
+        config.conservative_probe_rtt(conservative);
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let now = Instant::now();
+        bbr.max_bandwidth.seed(1_200_000);
+        bbr.min_rtt = Duration::from_millis(100);
+        bbr.probe_rtt_last_started_at = Some(now);
+        let now = now + K_DEFAULT_PROBE_RTT_INTERVAL + Duration::from_millis(1);
+        bbr.maybe_enter_or_exit_probe_rtt(now, true, 1_000_000, false);
+        assert_eq!(bbr.mode(), BbrMode::ProbeRtt);
+        assert_eq!(bbr.window(), bbr.inflight_target());
+        bbr
+    };
+
+    let bbr = probe_rtt(false);
+    assert_eq!(bbr.inflight_target(), 90_000);
+    let bbr = probe_rtt(true);
+    assert_eq!(bbr.inflight_target(), bbr.min_cwnd);
+}
+
+#[test]
+fn startup_growth_capped_per_round() {
+    let growth = |max_growth| {
+        let mut config = BbrConfig::default();
+        config.initial_window(10 * MTU as u64);
+        config.max_startup_growth(max_growth).unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let rtt = RttEstimator::new(Duration::from_millis(100));
+        let mut now = Instant::now();
+        let mut packet_number = 0;
+        let mut max_growth = 0.0f64;
+        for _ in 0..5 {
+            // Send a full window at once and have it acknowledged together a round trip later
+            let cwnd = bbr.window();
+            let sent = now;
+            let count = cwnd / MTU as u64;
+            for _ in 0..count {
+                bbr.on_sent(sent, MTU as u64, packet_number);
+                packet_number += 1;
+            }
+            now += rtt.get();
+            bbr.on_ack_range(now, sent, MTU as u64, count, false, &rtt);
+            bbr.on_end_acks(now, 0, false, Some(packet_number - 1));
+            if bbr.mode() != BbrMode::Startup {
+                break;
+            }
+            max_growth = max_growth.max(bbr.window() as f64 / cwnd as f64);
+        }
+        max_growth
+    };
+
+    assert!(BbrConfig::default().max_startup_growth(Some(0.5)).is_err());
+    assert!(growth(None) >= 2.0);
+    assert!(growth(Some(1.5)) <= 1.5);
+}
+
+#[test]
+fn end_acks_without_acknowledgements_keeps_state() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    bbr.on_sent(now, MTU as u64, 0);
+    bbr.on_congestion_event(now, now, false, MTU as u64);
+    let later = now + Duration::from_secs(30);
+    bbr.on_end_acks(later, 0, false, None);
+
+    assert_eq!(bbr.mode(), BbrMode::Startup);
+    assert_eq!(bbr.round_count, 0);
+    assert_eq!(bbr.window(), bbr.init_cwnd);
+    assert!(!bbr.recovery_state.in_recovery());
+    assert!(!bbr.is_at_full_bandwidth);
+    // Only time is accounted for
+    bbr.on_end_acks(later + Duration::from_secs(1), 0, false, None);
+    assert_eq!(bbr.mode_durations().startup, Duration::from_secs(1));
+}
+
+#[test]
+fn reports_queue_delay() {
+    let mut bbr = bbr();
+    assert_eq!(bbr.metrics().queue_delay, None);
+    let now = Instant::now();
+    let mut rtt = RttEstimator::new(Duration::from_millis(20));
+    rtt.update(Duration::ZERO, Duration::from_millis(20));
+    bbr.on_sent(now, MTU as u64, 0);
+    bbr.on_ack(
+        now + Duration::from_millis(20),
+        now,
+        MTU as u64,
+        false,
+        &rtt,
+    );
+    assert_eq!(bbr.metrics().queue_delay, Some(Duration::ZERO));
+
+    rtt.update(Duration::ZERO, Duration::from_millis(35));
+    bbr.on_sent(now, MTU as u64, 1);
+    bbr.on_ack(
+        now + Duration::from_millis(35),
+        now,
+        MTU as u64,
+        false,
+        &rtt,
+    );
+    assert_eq!(bbr.min_rtt, Duration::from_millis(20));
+    assert_eq!(bbr.metrics().queue_delay, Some(Duration::from_millis(15)));
+}
+
+#[test]
+fn cwnd_limited_near_inflight_target() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    let target = bbr.window().min(bbr.inflight_target());
+
+    assert!(!bbr.is_cwnd_limited(0));
+    assert!(!bbr.is_cwnd_limited(target / 2));
+    assert!(!bbr.is_cwnd_limited(target - MTU as u64));
+    assert!(bbr.is_cwnd_limited(target - MTU as u64 + 1));
+    assert!(bbr.is_cwnd_limited(target));
+    assert!(bbr.is_cwnd_limited(bbr.window()));
+
+    // Other controllers compare against their window
+    let cubic = Arc::new(crate::congestion::CubicConfig::default()).build(Instant::now(), MTU);
+    assert!(!cubic.is_cwnd_limited(cubic.window() - 1));
+    assert!(cubic.is_cwnd_limited(cubic.window()));
+}
+
+#[test]
+fn pacing_rate_follows_mode_transitions() {
+    let mut bbr = bbr();
+    bbr.max_bandwidth.seed(1_000_000);
+    bbr.min_rtt = Duration::from_millis(10);
+    bbr.calculate_pacing_rate();
+    assert!(bbr.pacing_rate >= 2_885_000);
+
+    bbr.is_at_full_bandwidth = true;
+    bbr.maybe_exit_startup_or_drain(Instant::now(), u64::MAX);
+    assert_eq!(bbr.mode(), BbrMode::Drain);
+    assert_eq!(bbr.pacing_gain, 1.0 / K_STARTUP_PACING_GAIN);
+    assert_eq!(bbr.pacing_rate, apply_gain(1_000_000, bbr.pacing_gain));
+
+    bbr.maybe_exit_startup_or_drain(Instant::now(), 0);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert_eq!(bbr.pacing_rate, apply_gain(1_000_000, bbr.pacing_gain));
+}
+
+#[test]
+fn probe_rtt_cwnd_gain_sets_window() {
+    let probe_rtt_window = |gain| {
+        let mut config = BbrConfig::default();
+        config.probe_rtt_cwnd_gain(gain).unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let now = Instant::now();
+        bbr.max_bandwidth.seed(1_200_000);
+        bbr.min_rtt = Duration::from_millis(100);
+        bbr.probe_rtt_last_started_at = Some(now);
+        let now = now + K_DEFAULT_PROBE_RTT_INTERVAL + Duration::from_millis(1);
+        bbr.maybe_enter_or_exit_probe_rtt(now, true, 1_000_000, false);
+        assert_eq!(bbr.mode(), BbrMode::ProbeRtt);
+        bbr.window()
+    };
+
+    assert!(BbrConfig::default().probe_rtt_cwnd_gain(0.0).is_err());
+    let bdp = 120_000;
+    assert_eq!(probe_rtt_window(0.5), bdp / 2);
+    assert_eq!(probe_rtt_window(0.75), bdp * 3 / 4);
+    // Never below the minimum window
+    assert_eq!(probe_rtt_window(0.001), 4 * MTU as u64);
+}
+
+#[test]
+fn cumulative_byte_counters() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.round(&mut bbr, 10);
+    assert_eq!(bbr.sent_bytes(), 10 * MTU as u64);
+    assert_eq!(bbr.delivered_bytes(), 10 * MTU as u64);
+    assert_eq!(bbr.lost_bytes(), 0);
+
+    path.lost_packets = 2;
+    for _ in 0..5 {
+        path.round(&mut bbr, 10);
+    }
+    assert_eq!(bbr.sent_bytes(), 60 * MTU as u64);
+    assert_eq!(bbr.delivered_bytes(), 50 * MTU as u64);
+    assert_eq!(bbr.lost_bytes(), 10 * MTU as u64);
+    // Unaffected by the per-round accounting being reset
+    assert_eq!(bbr.loss_state.lost_bytes, 0);
+}
+
+#[test]
+fn delivery_rate_uses_send_and_ack_intervals() {
+    let mut estimation = BandwidthEstimation::default();
+    let start = Instant::now();
+    let rtt = Duration::from_millis(50);
+    // 3600 bytes sent over 10ms, and acknowledged over 5ms
+    estimation.on_sent(start, 1200);
+    estimation.on_ack(start + rtt, start, 1200, 1, false);
+    estimation.on_sent(start + Duration::from_millis(10), 3600);
+    estimation.on_ack(
+        start + rtt + Duration::from_millis(5),
+        start + Duration::from_millis(10),
+        3600,
+        1,
+        false,
+    );
+    // The slower of the two rates, 3600 bytes / 10ms, is the delivery rate
+    assert_eq!(estimation.last_sample().unwrap().rate, 360_000);
+    assert_eq!(estimation.get_estimate().as_bytes_per_sec(), 360_000);
+
+    // Acknowledgements spread out further than the sends bound the rate instead
+    estimation.on_sent(start + Duration::from_millis(20), 1200);
+    estimation.on_ack(
+        start + rtt + Duration::from_millis(25),
+        start + Duration::from_millis(20),
+        1200,
+        2,
+        false,
+    );
+    assert_eq!(estimation.last_sample().unwrap().rate, 60_000);
+}
+
+#[test]
+fn recovery_window_cleared_after_recovery() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+
+    let now = path.now;
+    bbr.on_congestion_event(now, now, false, MTU as u64);
+    bbr.on_end_acks(now, bbr.cwnd, false, Some(path.next_packet_number - 1));
+    assert!(bbr.recovery_state.in_recovery());
+    assert!(bbr.window() < bbr.cwnd);
+
+    path.run(&mut bbr, 2);
+    assert!(!bbr.recovery_state.in_recovery());
+    assert_eq!(bbr.recovery_window, 0);
+    assert_eq!(bbr.window(), bbr.cwnd);
+}
+
+#[test]
+fn next_send_time_follows_pacing_rate() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    let rate = bbr.pacing_rate().unwrap();
+    assert_ne!(rate, 0);
+
+    let last_send = path.now;
+    let bytes = 10 * MTU as u64;
+    let next = bbr.next_send_time(last_send, last_send, bytes).unwrap();
+    let spacing = (next - last_send).as_nanos();
+    assert_eq!(
+        spacing,
+        u128::from(bytes) * 1_000_000_000 / u128::from(rate)
+    );
+
+    // Once the spacing has elapsed, sending may proceed immediately
+    let later = last_send + Duration::from_secs(1);
+    assert_eq!(bbr.next_send_time(later, last_send, bytes), Some(later));
+}
+
+/// Runs rounds with heavy loss while application-limited, so that STARTUP can't end because the
+/// bandwidth estimate plateaus
+fn lossy_startup(bbr: &mut Bbr) {
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.app_limited = true;
+    path.round(bbr, 20);
+    path.lost_packets = 3;
+    for _ in 0..3 {
+        path.round(bbr, 20);
+        if bbr.mode != BbrMode::Startup {
+            break;
+        }
+    }
+}
+
+#[test]
+fn startup_exits_on_heavy_loss() {
+    let mut bbr = bbr();
+    lossy_startup(&mut bbr);
+    // Little is in flight at the end of each round, so DRAIN may already be over
+    assert!(matches!(bbr.mode, BbrMode::Drain | BbrMode::ProbeBw));
+    assert!(bbr.is_at_full_bandwidth);
+}
+
+#[test]
+fn startup_loss_exit_can_be_disabled() {
+    let mut config = BbrConfig::default();
+    config.startup_loss_exit(None);
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    lossy_startup(&mut bbr);
+    assert_eq!(bbr.mode, BbrMode::Startup);
+}
+
+#[test]
+fn lifecycle_visits_every_mode() {
+    let observed = Arc::new(std::sync::Mutex::new(vec![]));
+    let mut config = BbrConfig::default();
+    config
+        .probe_rtt_interval(Duration::from_secs(1))
+        .on_mode_change(Some(Arc::new({
+            let observed = observed.clone();
+            move |mode| observed.lock().unwrap().push(mode)
+        })));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    bbr.random_number_generator = rand::rngs::StdRng::seed_from_u64(0);
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+
+    // (rounds to run, packets lost at the start of each, mode expected afterwards)
+    let timeline = [
+        (2, 0, BbrMode::Startup),
+        (1, 0, BbrMode::ProbeBw),
+        (3, 0, BbrMode::ProbeBw),
+        (1, 2, BbrMode::ProbeBw),
+        (10, 0, BbrMode::ProbeRtt),
+        (6, 0, BbrMode::ProbeBw),
+    ];
+    for (rounds, lost_packets, mode) in timeline {
+        path.lost_packets = lost_packets;
+        path.run(&mut bbr, rounds);
+        assert_eq!(bbr.mode(), mode);
+    }
+
+    assert_eq!(
+        *observed.lock().unwrap(),
+        [
+            BbrMode::Drain,
+            BbrMode::ProbeBw,
+            BbrMode::ProbeRtt,
+            BbrMode::ProbeBw
+        ]
+    );
+    let bdp = bbr.get_target_cwnd(1.0);
+    assert_eq!(bdp, 240_000);
+    assert!(bbr.window() >= bdp && bbr.window() <= 2 * bdp);
+}
+
+#[test]
+fn fixed_point_gains_match_float() {
+    let gains = [
+        K_STARTUP_PACING_GAIN,
+        1.0 / K_STARTUP_PACING_GAIN,
+        K_DEFAULT_RECOVERY_BETA,
+        1.25,
+        0.75,
+        1.0,
+    ];
+    for gain in gains {
+        for value in [0, 1_200, 48_000, 12_000_000, 24_000_000_000_000] {
+            let fixed = apply_gain_fixed(value, gain);
+            let float = apply_gain_float(value, gain);
+            assert!(
+                fixed.abs_diff(float) <= 1 + float / 10_000,
+                "{value} * {gain}: {fixed} != {float}"
+            );
+        }
+    }
+
+    // The resulting windows agree as well
+    let mut bbr = bbr();
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    let bw = bbr.max_bandwidth.get_estimate().as_bytes_per_sec();
+    let bdp = bbr.min_rtt.as_micros() as u64 * bw;
+    let fixed = apply_gain_fixed(bdp, bbr.cwnd_gain) / 1_000_000;
+    let float = apply_gain_float(bdp, bbr.cwnd_gain) / 1_000_000;
+    assert!(fixed.abs_diff(float) <= float / 10_000);
+    assert_eq!(
+        bbr.get_target_cwnd(bbr.cwnd_gain),
+        apply_gain(bdp, bbr.cwnd_gain) / 1_000_000
+    );
+}
+
+#[test]
+fn reports_bdp() {
+    let mut bbr = bbr();
+    assert_eq!(bbr.metrics().bdp, None);
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.bandwidth().as_bytes_per_sec(), 12_000_000);
+    assert_eq!(bbr.min_rtt, Duration::from_millis(20));
+    // 12 MB/s * 20 ms
+    assert_eq!(bbr.metrics().bdp, Some(240_000));
+}
+
+#[test]
+fn full_bandwidth_only_reset_on_path_change() {
+    let mut bbr = bbr();
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    path.run(&mut bbr, 5);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert!(bbr.is_at_full_bandwidth);
+
+    // Gain cycling, losses and bandwidth changes leave it latched
+    for round in 0..40 {
+        path.lost_packets = u64::from(round % 7 == 0);
+        path.bandwidth = [12_000_000, 6_000_000, 24_000_000][round % 3];
+        path.run(&mut bbr, 1);
+        assert!(bbr.is_at_full_bandwidth);
+        assert_ne!(bbr.mode(), BbrMode::Startup);
+    }
+
+    let bandwidth = bbr.bandwidth();
+    bbr.on_path_change();
+    assert!(!bbr.is_at_full_bandwidth);
+    assert_eq!(bbr.mode(), BbrMode::Startup);
+    assert_eq!(bbr.bandwidth(), bandwidth);
+}
+
+#[test]
+fn max_probe_pacing_gain_caps_probing() {
+    assert!(
+        BbrConfig::default()
+            .max_probe_pacing_gain(Some(0.9))
+            .is_err()
+    );
+    let mut config = BbrConfig::default();
+    config.max_probe_pacing_gain(Some(1.1)).unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    path.run(&mut bbr, 5);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+
+    let mut gains = Vec::new();
+    for _ in 0..20 {
+        path.run(&mut bbr, 1);
+        assert!(bbr.pacing_gain <= 1.1);
+        gains.push(bbr.pacing_gain);
+    }
+    assert!(gains.contains(&1.1));
+}
+
+#[test]
+fn hybrid_cwnd_backs_off_on_loss() {
+    let run = |hybrid| {
+        let mut config = BbrConfig::default();
+        config.hybrid_cwnd(hybrid);
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        bbr.random_number_generator = rand::rngs::StdRng::seed_from_u64(0);
+        let mut path = Path::new(12_000_000, Duration::from_millis(20));
+        path.run(&mut bbr, 10);
+        let before = bbr.window();
+        path.lost_packets = 1;
+        path.run(&mut bbr, 6);
+        (before, bbr.window())
+    };
+
+    let (bbr_before, bbr_after) = run(false);
+    let (hybrid_before, hybrid_after) = run(true);
+    // Without losses the model limits the window either way
+    assert_eq!(hybrid_before, bbr_before);
+    // Losses in every round halve the loss-based window each time, while the model is unmoved
+    assert_eq!(bbr_after, bbr_before);
+    assert!(hybrid_after < bbr_after / 4);
+}
+
+#[test]
+fn max_sent_packet_number_never_regresses() {
+    let mut bbr = bbr();
+    let now = Instant::now();
+    bbr.on_sent(now, MTU as u64, 10);
+    bbr.on_sent(now, MTU as u64, 4);
+    assert_eq!(bbr.max_sent_packet_number, 10);
+    bbr.on_sent(now, MTU as u64, 11);
+    assert_eq!(bbr.max_sent_packet_number, 11);
+}
+
+#[test]
+fn window_override() {
+    let mut bbr = bbr();
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    bbr.set_window_override(Some(5_000));
+    assert_eq!(bbr.window(), 5_000);
+    path.run(&mut bbr, 10);
+    assert_eq!(bbr.window(), 5_000);
+    path.lost_packets = 1;
+    path.run(&mut bbr, 2);
+    assert_eq!(bbr.window(), 5_000);
+
+    bbr.set_window_override(None);
+    assert_ne!(bbr.window(), 5_000);
+}
+
+#[test]
+fn bandwidth_samples_age_out() {
+    assert!(BbrConfig::default().bandwidth_window_rounds(0).is_err());
+    let estimate_after_drop = |window_rounds| {
+        let mut config = BbrConfig::default();
+        config.bandwidth_window_rounds(window_rounds).unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let mut path = Path::new(12_000_000, Duration::from_millis(20));
+        path.run(&mut bbr, 10);
+        assert_eq!(bbr.bandwidth().as_bytes_per_sec(), 12_000_000);
+        path.bandwidth = 3_000_000;
+        path.run(&mut bbr, 6);
+        bbr.bandwidth().as_bytes_per_sec()
+    };
+
+    assert_eq!(estimate_after_drop(4), 3_000_000);
+    assert_eq!(estimate_after_drop(100), 12_000_000);
+}
+
+#[test]
+fn loss_callback_reports_round_loss_rate() {
+    let observed = Arc::new(std::sync::Mutex::new(vec![]));
+    let mut config = BbrConfig::default();
+    config.on_loss(Some(Arc::new({
+        let observed = observed.clone();
+        move |rate| observed.lock().unwrap().push(rate)
+    })));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    assert!(observed.lock().unwrap().is_empty());
+
+    // A round of 20 packets, the first 5 of which are lost
+    path.lost_packets = 5;
+    path.round(&mut bbr, 20);
+    let observed = observed.lock().unwrap();
+    assert_eq!(observed.len(), 5);
+    // The losses come on top of the packets acknowledged earlier in the controller's round
+    let acked_packets = 1.0 / observed[0] - 1.0;
+    assert!(acked_packets > 0.0);
+    for (i, &rate) in observed.iter().enumerate() {
+        let lost_packets = (i + 1) as f64;
+        let expected = lost_packets / (lost_packets + acked_packets);
+        assert!((rate - expected).abs() < 1e-9, "{rate} != {expected}");
+    }
+}
+
+#[test]
+fn loss_callback_ignores_ecn() {
+    let observed = Arc::new(std::sync::Mutex::new(vec![]));
+    let mut config = BbrConfig::default();
+    config.on_loss(Some(Arc::new({
+        let observed = observed.clone();
+        move |rate| observed.lock().unwrap().push(rate)
+    })));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+
+    // Congestion experienced marks, without any loss
+    bbr.on_congestion_event(path.now, path.now, false, 0);
+    assert!(observed.lock().unwrap().is_empty());
+    bbr.on_congestion_event(path.now, path.now, false, MTU as u64);
+    assert_eq!(observed.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn initial_window_clamped_to_max_window() {
+    let mut config = BbrConfig::default();
+    config.initial_window(100_000).max_window(50_000).unwrap();
+    let controller = Arc::new(config).build(Instant::now(), MTU);
+    assert_eq!(controller.initial_window(), 50_000);
+    assert_eq!(controller.window(), 50_000);
+
+    // Regardless of the order in which they're set
+    let mut config = BbrConfig::default();
+    config.max_window(50_000).unwrap().initial_window(100_000);
+    let controller = Arc::new(config).build(Instant::now(), MTU);
+    assert_eq!(controller.initial_window(), 50_000);
+}
+
+#[test]
+fn drain_waits_for_rtt_to_normalize() {
+    let drain = |tolerance| {
+        let mut config = BbrConfig::default();
+        config.drain_rtt_tolerance(tolerance).unwrap();
+        config.max_drain_rounds(u64::MAX);
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        bbr.max_bandwidth.seed(1_000_000);
+        bbr.min_rtt = Duration::from_millis(20);
+        bbr.smoothed_rtt = Duration::from_millis(40);
+        bbr.is_at_full_bandwidth = true;
+        bbr.maybe_exit_startup_or_drain(Instant::now(), u64::MAX);
+        assert_eq!(bbr.mode(), BbrMode::Drain);
+        bbr
+    };
+    assert!(
+        BbrConfig::default()
+            .drain_rtt_tolerance(Some(-1.0))
+            .is_err()
+    );
+
+    // Nothing in flight, but the queueing delay persists
+    let mut bbr = drain(Some(0.1));
+    for _ in 0..10 {
+        bbr.maybe_exit_startup_or_drain(Instant::now(), 0);
+        assert_eq!(bbr.mode(), BbrMode::Drain);
+    }
+    bbr.smoothed_rtt = Duration::from_millis(21);
+    bbr.maybe_exit_startup_or_drain(Instant::now(), 0);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+
+    // By default, only the bytes in flight matter
+    let mut bbr = drain(None);
+    bbr.maybe_exit_startup_or_drain(Instant::now(), 0);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+}
+
+#[test]
+fn probe_bw_phase_follows_gain_cycle() {
+    let mut bbr = bbr();
+    assert_eq!(bbr.probe_bw_phase(), None);
+
+    bbr.max_bandwidth.seed(1_000_000);
+    bbr.min_rtt = Duration::from_millis(10);
+    bbr.enter_probe_bandwidth_mode();
+    // Restart the cycle from its first phase
+    bbr.current_cycle_offset = K_PACING_GAIN.len() - 1;
+    let mut phases = Vec::new();
+    for _ in 0..K_PACING_GAIN.len() {
+        bbr.advance_gain_cycle_phase();
+        phases.push(bbr.probe_bw_phase().unwrap());
+        assert_eq!(bbr.metrics().probe_bw_phase, bbr.probe_bw_phase());
+    }
+    let mut expected = vec![ProbeBwPhase::Up, ProbeBwPhase::Down];
+    expected.resize(K_PACING_GAIN.len(), ProbeBwPhase::Unity);
+    assert_eq!(phases, expected);
+
+    bbr.on_idle(K_DEFAULT_PROBE_RTT_INTERVAL);
+    assert_eq!(bbr.mode(), BbrMode::Startup);
+    assert_eq!(bbr.probe_bw_phase(), None);
+    assert_eq!(bbr.metrics().probe_bw_phase, None);
+}
+
+#[test]
+fn startup_exit_thresholds() {
+    assert!(BbrConfig::default().startup_growth_target(1.0).is_err());
+    assert!(BbrConfig::default().startup_full_bw_rounds(0).is_err());
+    // Round in which STARTUP ends on a path whose bandwidth grows by `growth` every round, if it
+    // ends within 50 rounds
+    let exit_round = |target, rounds, growth: f64| {
+        let mut config = BbrConfig::default();
+        config
+            .startup_growth_target(target)
+            .unwrap()
+            .startup_full_bw_rounds(rounds)
+            .unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let mut path = Path::new(1_200_000, Duration::from_millis(20));
+        while bbr.mode() == BbrMode::Startup && bbr.round_count < 50 {
+            path.bandwidth = (path.bandwidth as f64 * growth) as u64;
+            path.round(&mut bbr, 10);
+        }
+        (bbr.mode() != BbrMode::Startup).then_some(bbr.round_count)
+    };
+
+    // On a plateau, STARTUP lasts as many rounds as configured
+    let exits = (1..=4)
+        .map(|rounds| exit_round(1.25, rounds, 1.0))
+        .collect::<Vec<_>>();
+    assert_eq!(exits, [Some(3), Some(4), Some(5), Some(6)]);
+
+    // Growth below the target counts as a plateau
+    assert_eq!(exit_round(1.25, 2, 1.1), Some(4));
+    assert_eq!(exit_round(1.05, 2, 1.1), None);
+}
+
+#[test]
+fn short_pacing_gain_cycles() {
+    let mut config = BbrConfig::default();
+    assert!(config.pacing_gain_cycle(Some(vec![])).is_err());
+    assert!(config.pacing_gain_cycle(Some(vec![1.25])).is_err());
+    // A rejected cycle leaves the default in place
+    assert_eq!(
+        Bbr::new(Arc::new(config.clone()), MTU)
+            .parameters()
+            .pacing_gain_cycle,
+        K_PACING_GAIN
+    );
+
+    // The shortest valid cycle can be entered at any point without going out of bounds
+    config.pacing_gain_cycle(Some(vec![1.25, 0.75])).unwrap();
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    for seed in 0..16 {
+        bbr.random_number_generator = rand::rngs::StdRng::seed_from_u64(seed);
+        bbr.enter_probe_bandwidth_mode();
+        assert_eq!(bbr.pacing_gain, 1.25);
+        bbr.advance_gain_cycle_phase();
+        assert_eq!(bbr.pacing_gain, 0.75);
+        bbr.advance_gain_cycle_phase();
+        assert_eq!(bbr.pacing_gain, 1.25);
+    }
+}
+
+#[test]
+fn recovery_lasts_configured_clean_rounds() {
+    assert!(BbrConfig::default().recovery_exit_rounds(0).is_err());
+    // Rounds spent in recovery after a single loss
+    let recovery_rounds = |exit_rounds| {
+        let mut config = BbrConfig::default();
+        config.recovery_exit_rounds(exit_rounds).unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let mut path = Path::new(1_200_000, Duration::from_millis(20));
+        path.run(&mut bbr, 10);
+
+        let now = path.now;
+        bbr.on_congestion_event(now, now, false, MTU as u64);
+        bbr.on_end_acks(now, bbr.cwnd, false, Some(path.next_packet_number - 1));
+        assert!(bbr.recovery_state.in_recovery());
+        let entered_at = bbr.round_count;
+        while bbr.recovery_state.in_recovery() {
+            assert!(bbr.round_count < entered_at + 20);
+            path.round(&mut bbr, 1);
+        }
+        bbr.round_count - entered_at
+    };
+
+    assert_eq!(recovery_rounds(1), 1);
+    assert_eq!(recovery_rounds(3), 3);
+}
+
+#[test]
+fn gains_follow_mode() {
+    let mut bbr = bbr();
+    assert_eq!(
+        bbr.gains(),
+        Gains {
+            pacing: K_STARTUP_PACING_GAIN,
+            cwnd: K_STARTUP_CWND_GAIN,
+        }
+    );
+
+    let now = Instant::now();
+    bbr.max_bandwidth.seed(1_000_000);
+    bbr.min_rtt = Duration::from_millis(10);
+    bbr.is_at_full_bandwidth = true;
+    bbr.maybe_exit_startup_or_drain(now, u64::MAX);
+    assert_eq!(bbr.mode(), BbrMode::Drain);
+    assert_eq!(bbr.gains().pacing, 1.0 / K_STARTUP_PACING_GAIN);
+    assert_eq!(bbr.gains().cwnd, K_STARTUP_CWND_GAIN);
+
+    bbr.maybe_exit_startup_or_drain(now, 0);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert_eq!(bbr.gains().cwnd, K_PROBE_BW_CWND_GAIN);
+    assert!(K_PACING_GAIN.contains(&bbr.gains().pacing));
+
+    bbr.probe_rtt_last_started_at = Some(now);
+    bbr.maybe_enter_or_exit_probe_rtt(now + K_DEFAULT_PROBE_RTT_INTERVAL * 2, true, 0, false);
+    assert_eq!(bbr.mode(), BbrMode::ProbeRtt);
+    assert_eq!(
+        bbr.gains(),
+        Gains {
+            pacing: 1.0,
+            cwnd: K_MODERATE_PROBE_RTT_MULTIPLIER,
+        }
+    );
+}
+
+#[test]
+fn min_rtt_floor_rejects_outliers() {
+    assert!(BbrConfig::default().min_rtt_floor(Some(1.5)).is_err());
+    assert!(BbrConfig::default().min_rtt_floor(Some(f32::NAN)).is_err());
+    let min_rtt_after_outlier = |floor| {
+        let mut config = BbrConfig::default();
+        config.min_rtt_floor(floor).unwrap();
+        let mut bbr = Bbr::new(Arc::new(config), MTU);
+        let mut path = Path::new(1_200_000, Duration::from_millis(20));
+        path.run(&mut bbr, 5);
+        assert_eq!(bbr.min_rtt, Duration::from_millis(20));
+
+        path.rtt.update(Duration::ZERO, Duration::from_micros(10));
+        let now = path.now;
+        bbr.on_sent(now, MTU as u64, path.next_packet_number);
+        bbr.on_ack(now, now, MTU as u64, false, &path.rtt);
+        (bbr, path)
+    };
+
+    assert_eq!(
+        min_rtt_after_outlier(None).0.min_rtt,
+        Duration::from_micros(10)
+    );
+    let (mut bbr, mut path) = min_rtt_after_outlier(Some(0.5));
+    assert_eq!(bbr.min_rtt, Duration::from_millis(20));
+    // The estimator's own minimum is now pinned at the outlier
+    assert_eq!(path.rtt.min(), Duration::from_micros(10));
+
+    // A plausible lower sample, e.g. after a route change, still lowers the minimum
+    path.min_rtt = Duration::from_millis(15);
+    path.run(&mut bbr, 1);
+    assert_eq!(bbr.min_rtt, Duration::from_millis(15));
+
+    // And once it expires, the minimum is still refreshed, even to a higher value
+    path.min_rtt = Duration::from_millis(25);
+    path.now += K_DEFAULT_PROBE_RTT_INTERVAL;
+    path.run(&mut bbr, 1);
+    assert_eq!(bbr.min_rtt, Duration::from_millis(25));
+}
+
+#[test]
+fn rate_samples_drained_in_order() {
+    let mut bbr = Bbr::new(Arc::new(BbrConfig::default()), MTU);
+    let mut path = Path::new(12_000_000, Duration::from_millis(20));
+    path.run(&mut bbr, 3);
+    assert!(bbr.take_samples().is_empty());
+
+    let mut config = BbrConfig::default();
+    config.rate_sample_history(8);
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    path.run(&mut bbr, 3);
+    let samples = bbr.take_samples();
+    assert_eq!(samples.len(), 8);
+    assert!(samples.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    let latest = samples.last().unwrap();
+    assert_eq!(latest.time, path.now);
+    assert_eq!(
+        Some(latest.delivery_rate.as_bits_per_sec()),
+        bbr.metrics().delivery_rate
+    );
+    assert!(bbr.take_samples().is_empty());
+
+    path.run(&mut bbr, 1);
+    assert!(
+        bbr.take_samples()
+            .iter()
+            .all(|sample| sample.time > latest.time)
+    );
+}
+
+#[test]
+fn simultaneous_acks_record_one_sample() {
+    let mut config = BbrConfig::default();
+    config.rate_sample_history(16);
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let rtt = RttEstimator::new(Duration::from_millis(20));
+    let start = Instant::now();
+    for packet_number in 0..6 {
+        bbr.on_sent(start, MTU as u64, packet_number);
+    }
+    // The first acknowledgement has no interval to measure a rate over
+    let now = start + Duration::from_millis(20);
+    bbr.on_ack(now, start, MTU as u64, false, &rtt);
+    bbr.on_end_acks(now, 5 * MTU as u64, false, Some(0));
+    assert!(bbr.take_samples().is_empty());
+
+    // Only the first of several acknowledgements arriving together yields a sample
+    let now = now + Duration::from_millis(1);
+    for _ in 0..5 {
+        bbr.on_ack(now, start, MTU as u64, false, &rtt);
+    }
+    bbr.on_end_acks(now, 0, false, Some(5));
+    let samples = bbr.take_samples();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].time, now);
+    assert_ne!(samples[0].delivery_rate.as_bytes_per_sec(), 0);
+}
+
+#[test]
+fn packet_interval_pacing() {
+    let mut config = BbrConfig::default();
+    config.pacing_mode(PacingMode::PacketInterval);
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    assert_eq!(bbr.packet_interval(), None);
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.run(&mut bbr, 10);
+    let rate = bbr.pacing_rate().unwrap();
+    let interval = bbr.packet_interval().unwrap();
+    assert_eq!(
+        interval.as_nanos(),
+        u128::from(MTU) * 1_000_000_000 / u128::from(rate)
+    );
+
+    // Partial packets take up a whole interval
+    let last_send = path.now;
+    assert_eq!(
+        bbr.next_send_time(last_send, last_send, 2 * MTU as u64 + 1),
+        Some(last_send + 3 * interval)
+    );
+}
+
+#[test]
+fn bandwidth_confidence_rises_with_full_samples() {
+    let mut bbr = bbr();
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    assert_eq!(bbr.bandwidth_confidence(), 0.0);
+    path.app_limited = true;
+    for _ in 0..3 {
+        path.round(&mut bbr, 10);
+    }
+    assert_ne!(bbr.bandwidth().as_bytes_per_sec(), 0);
+    assert_eq!(bbr.bandwidth_confidence(), 0.0);
+    assert_eq!(bbr.export_state(), None);
+
+    // Each round of 10 packets spans less than a round trip, so it's one round for the controller
+    path.app_limited = false;
+    let mut confidence = vec![];
+    for _ in 0..15 {
+        path.round(&mut bbr, 10);
+        confidence.push(bbr.bandwidth_confidence());
+    }
+    assert!(confidence.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(confidence[0], 0.1);
+    assert_eq!(*confidence.last().unwrap(), 1.0);
+    assert!(bbr.export_state().is_some());
+
+    // A seeded estimate is used, and survives short idle periods, but isn't exported
+    let mut config = BbrConfig::default();
+    config.skip_startup(1_200_000, Duration::from_millis(20));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert_eq!(bbr.bandwidth_confidence(), 0.0);
+    bbr.on_idle(Duration::from_millis(100));
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert_eq!(bbr.bandwidth().as_bytes_per_sec(), 1_200_000);
+    assert_eq!(bbr.export_state(), None);
+}
+
+#[test]
+fn follows_synthetic_clock() {
+    // Far enough from the wall clock that any direct use of it would skew the results
+    let start = Instant::now() + Duration::from_secs(24 * 60 * 60);
+    let mut config = BbrConfig::default();
+    config.max_startup_duration(Duration::from_millis(100));
+    let mut bbr = Bbr::new_at(Arc::new(config), MTU, start);
+    let mut path = Path::new(1_200_000, Duration::from_millis(20));
+    path.now = start;
+    path.app_limited = true;
+    path.round(&mut bbr, 10);
+    assert_eq!(bbr.mode(), BbrMode::Startup);
+    assert_eq!(bbr.mode_durations().total(), path.now - start);
+
+    // STARTUP is timed from construction rather than from the first acknowledgement
+    while path.now - start < Duration::from_millis(100) {
+        assert_eq!(bbr.mode(), BbrMode::Startup);
+        path.round(&mut bbr, 10);
+    }
+    assert_ne!(bbr.mode(), BbrMode::Startup);
+    assert_eq!(bbr.mode_durations().total(), path.now - start);
+}
+
+#[test]
+fn probing_ends_once_inflight_target_reached() {
+    let mut bbr = bbr();
+    bbr.max_bandwidth.seed(1_000_000);
+    bbr.min_rtt = Duration::from_millis(10);
+    bbr.enter_probe_bandwidth_mode();
+    bbr.current_cycle_offset = K_PACING_GAIN.len() - 1;
+    bbr.advance_gain_cycle_phase();
+    assert_eq!(bbr.probe_bw_phase(), Some(ProbeBwPhase::Up));
+    let target = bbr.get_target_cwnd(bbr.pacing_gain);
+
+    // A round trip has passed without losses, but the probe hasn't filled the pipe yet
+    bbr.round_count += 1;
+    bbr.prev_in_flight_count = target - 1;
+    bbr.update_gain_cycle_phase(target - 1);
+    assert_eq!(bbr.probe_bw_phase(), Some(ProbeBwPhase::Up));
+
+    bbr.prev_in_flight_count = target;
+    bbr.update_gain_cycle_phase(target);
+    assert!(!bbr.loss_state.has_losses());
+    assert_eq!(bbr.probe_bw_phase(), Some(ProbeBwPhase::Down));
+}
+
+#[test]
+fn goodput_over_trailing_window() {
+    let mut config = BbrConfig::default();
+    config.goodput_history(Duration::from_millis(500));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let rtt = RttEstimator::new(Duration::from_millis(20));
+    assert_eq!(bbr.goodput(Duration::from_millis(100)), 0);
+
+    // One packet delivered every 10ms for a second
+    let start = Instant::now();
+    for i in 0..100 {
+        let sent = start + Duration::from_millis(10 * i);
+        let acked = sent + Duration::from_millis(20);
+        bbr.on_sent(sent, MTU as u64, i);
+        bbr.on_ack(acked, sent, MTU as u64, false, &rtt);
+        bbr.on_end_acks(acked, 0, false, Some(i));
+    }
+    assert_eq!(
+        bbr.goodput(Duration::from_millis(100)),
+        10 * MTU as u64 * 10
+    );
+    assert_eq!(bbr.goodput(Duration::from_millis(200)), 20 * MTU as u64 * 5);
+    // Windows beyond the history are clamped
+    assert_eq!(bbr.goodput(Duration::from_secs(10)), 50 * MTU as u64 * 2);
+    assert_eq!(bbr.goodput(Duration::ZERO), 0);
+}
+
+#[test]
+fn goodput_over_long_fast_window() {
+    let mut config = BbrConfig::default();
+    config.goodput_history(Duration::from_secs(20));
+    let mut bbr = Bbr::new(Arc::new(config), MTU);
+    let rtt = RttEstimator::new(Duration::from_millis(20));
+
+    // 25 GB delivered within 20 seconds, i.e. 10 Gbps
+    let start = Instant::now();
+    let end = start + Duration::from_secs(20);
+    let delivered = 25_000_000_000;
+    bbr.on_sent(start, MTU as u64, 0);
+    bbr.on_sent(end - Duration::from_millis(20), delivered, 1);
+    bbr.on_ack(
+        start + Duration::from_millis(20),
+        start,
+        MTU as u64,
+        false,
+        &rtt,
+    );
+    bbr.on_end_acks(start + Duration::from_millis(20), delivered, false, Some(0));
+    bbr.on_ack(
+        end + Duration::from_millis(20),
+        end - Duration::from_millis(20),
+        delivered,
+        false,
+        &rtt,
+    );
+    bbr.on_end_acks(end + Duration::from_millis(20), 0, false, Some(1));
+    assert_eq!(bbr.goodput(Duration::from_secs(20)), delivered / 20);
+}
+
+#[test]
+fn bw_from_delta_saturates() {
+    assert_eq!(
+        BandwidthEstimation::bw_from_delta(u64::MAX, Duration::from_nanos(1)),
+        Some(u64::MAX)
+    );
+    assert_eq!(
+        BandwidthEstimation::bw_from_delta(25_000_000_000, Duration::from_secs(20)),
+        Some(1_250_000_000)
+    );
+    assert_eq!(BandwidthEstimation::bw_from_delta(1, Duration::ZERO), None);
+}
@@ -224,10 +224,14 @@ impl Controller for Cubic {
         super::ControllerMetrics {
             congestion_window: self.window(),
             ssthresh: Some(self.ssthresh),
-            pacing_rate: None,
+            ..Default::default()
         }
     }
 
+    fn name(&self) -> &'static str {
+        "cubic"
+    }
+
     fn clone_box(&self) -> Box<dyn Controller> {
         Box::new(self.clone())
     }
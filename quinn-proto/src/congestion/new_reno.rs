@@ -116,10 +116,14 @@ impl Controller for NewReno {
         super::ControllerMetrics {
             congestion_window: self.window(),
             ssthresh: Some(self.ssthresh),
-            pacing_rate: None,
+            ..Default::default()
         }
     }
 
+    fn name(&self) -> &'static str {
+        "new_reno"
+    }
+
     fn clone_box(&self) -> Box<dyn Controller> {
         Box::new(self.clone())
     }
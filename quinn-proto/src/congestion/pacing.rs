@@ -0,0 +1,141 @@
+use crate::{Duration, Instant};
+
+/// Token bucket spacing out transmissions at a congestion controller's pacing rate
+///
+/// Controllers such as [`Bbr`](super::Bbr) only report the rate at which they would like data
+/// to be sent, via [`Controller::pacing_rate`](super::Controller::pacing_rate). This helper turns
+/// that rate into send decisions for callers driving a controller directly: the bucket refills
+/// at the pacing rate, holds at most `max_burst` bytes, and permits a send whenever it holds at
+/// least one full-sized packet.
+#[derive(Debug, Clone)]
+pub struct PacingController {
+    /// Refill rate in bytes per second, or 0 to disable pacing
+    rate: u64,
+    max_burst: u64,
+    mtu: u64,
+    tokens: u64,
+    updated_at: Instant,
+}
+
+impl PacingController {
+    /// Construct a pacer starting out with a full burst of `max_burst` bytes
+    ///
+    /// Pacing is disabled until a rate is set with [`set_pacing_rate`](Self::set_pacing_rate).
+    /// `max_burst` is raised to `mtu` if smaller, as the bucket must be able to hold a full-sized
+    /// packet for sending to ever be permitted.
+    pub fn new(now: Instant, max_burst: u64, mtu: u16) -> Self {
+        let max_burst = max_burst.max(mtu.into());
+        Self {
+            rate: 0,
+            max_burst,
+            mtu: mtu.into(),
+            tokens: max_burst,
+            updated_at: now,
+        }
+    }
+
+    /// Refill at `rate` bytes per second from now on, e.g. the controller's latest pacing rate
+    ///
+    /// Tokens accumulated so far are credited at the previous rate. A rate of 0 disables pacing.
+    pub fn set_pacing_rate(&mut self, now: Instant, rate: u64) {
+        self.refill(now);
+        self.rate = rate;
+    }
+
+    /// Record that `bytes` were sent at `now`
+    pub fn on_sent(&mut self, now: Instant, bytes: u64) {
+        self.refill(now);
+        self.tokens = self.tokens.saturating_sub(bytes);
+    }
+
+    /// Whether a full-sized packet may be sent at `now` without exceeding the pacing rate
+    pub fn can_send(&self, now: Instant) -> bool {
+        self.rate == 0 || self.tokens_at(now) >= self.mtu
+    }
+
+    /// Time to wait after `now` until a full-sized packet may be sent
+    pub fn time_until_send(&self, now: Instant) -> Duration {
+        let tokens = self.tokens_at(now);
+        if self.rate == 0 || tokens >= self.mtu {
+            return Duration::ZERO;
+        }
+        let deficit = u128::from(self.mtu - tokens);
+        let nanos = (deficit * 1_000_000_000).div_ceil(u128::from(self.rate));
+        Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX))
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.tokens = self.tokens_at(now);
+        self.updated_at = self.updated_at.max(now);
+    }
+
+    fn tokens_at(&self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        let refill = u128::from(self.rate) * elapsed.as_nanos() / 1_000_000_000;
+        (u128::from(self.tokens) + refill).min(u128::from(self.max_burst)) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTU: u16 = 1200;
+
+    #[test]
+    fn refills_at_pacing_rate() {
+        let now = Instant::now();
+        let mut pacer = PacingController::new(now, 2 * MTU as u64, MTU);
+        pacer.set_pacing_rate(now, 1_200_000);
+        pacer.on_sent(now, 2 * MTU as u64);
+        assert!(!pacer.can_send(now));
+        // One packet's worth of tokens takes a millisecond at 1.2 MB/s
+        assert_eq!(pacer.time_until_send(now), Duration::from_millis(1));
+        let half = now + Duration::from_micros(500);
+        assert!(!pacer.can_send(half));
+        assert_eq!(pacer.time_until_send(half), Duration::from_micros(500));
+
+        let later = now + Duration::from_millis(1);
+        assert!(pacer.can_send(later));
+        assert_eq!(pacer.time_until_send(later), Duration::ZERO);
+        pacer.on_sent(later, MTU as u64);
+        assert!(!pacer.can_send(later));
+    }
+
+    #[test]
+    fn limits_bursts() {
+        let now = Instant::now();
+        let mut pacer = PacingController::new(now, 3 * MTU as u64, MTU);
+        pacer.set_pacing_rate(now, 1_200_000);
+        // A long idle period doesn't accumulate more than the burst budget
+        let later = now + Duration::from_secs(1);
+        for _ in 0..3 {
+            assert!(pacer.can_send(later));
+            pacer.on_sent(later, MTU as u64);
+        }
+        assert!(!pacer.can_send(later));
+        assert_eq!(pacer.time_until_send(later), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn burst_holds_at_least_one_packet() {
+        let now = Instant::now();
+        let mut pacer = PacingController::new(now, 0, MTU);
+        pacer.set_pacing_rate(now, 1_200_000);
+        assert!(pacer.can_send(now));
+        pacer.on_sent(now, MTU as u64);
+        assert!(!pacer.can_send(now));
+        let later = now + Duration::from_secs(1);
+        assert!(pacer.can_send(later));
+        assert_eq!(pacer.time_until_send(later), Duration::ZERO);
+    }
+
+    #[test]
+    fn unpaced_without_rate() {
+        let now = Instant::now();
+        let mut pacer = PacingController::new(now, MTU as u64, MTU);
+        pacer.on_sent(now, 10 * MTU as u64);
+        assert!(pacer.can_send(now));
+        assert_eq!(pacer.time_until_send(now), Duration::ZERO);
+    }
+}
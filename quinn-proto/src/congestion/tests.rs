@@ -0,0 +1,155 @@
+//! Harness driving congestion controllers through simulated network scenarios
+
+use super::*;
+use crate::Duration;
+
+const MTU: u16 = 1200;
+
+/// A path with a bottleneck link of fixed bandwidth followed by a drop-tail buffer
+///
+/// Each round, the controller's whole window is sent paced at the bottleneck rate, and every
+/// packet which isn't dropped is acknowledged a round trip after it was sent.
+struct Scenario {
+    /// Bottleneck bandwidth in bytes per second
+    bandwidth: u64,
+    rtt: Duration,
+    /// Bytes which can be queued at the bottleneck on top of the BDP before packets are dropped
+    buffer: u64,
+    /// Additionally drop every n-th packet
+    loss_interval: Option<u64>,
+    rounds: usize,
+}
+
+impl Scenario {
+    fn bdp(&self) -> u64 {
+        (self.bandwidth as u128 * self.rtt.as_nanos() / 1_000_000_000) as u64
+    }
+
+    /// Drives `controller` through the scenario, returning its window at the end of each round
+    fn run(&self, mut controller: Box<dyn Controller>) -> Vec<u64> {
+        let bytes = MTU as u64;
+        let interval = Duration::from_nanos(bytes * 1_000_000_000 / self.bandwidth);
+        let capacity = (self.bdp() + self.buffer) / bytes;
+        let mut rtt = RttEstimator::new(self.rtt);
+        let mut now = Instant::now();
+        let mut next_packet_number = 0;
+
+        let mut windows = Vec::with_capacity(self.rounds);
+        for _ in 0..self.rounds {
+            let packets = (controller.window() / bytes).max(1);
+            let start = now;
+            let sent = (0..packets)
+                .map(|i| {
+                    let sent_at = start + interval * i as u32;
+                    controller.on_sent(sent_at, bytes, next_packet_number);
+                    next_packet_number += 1;
+                    (sent_at, next_packet_number - 1)
+                })
+                .collect::<Vec<_>>();
+
+            let mut in_flight = packets * bytes;
+            let mut lost = None;
+            let mut lost_bytes = 0;
+            for (i, (sent_at, packet_number)) in sent.into_iter().enumerate() {
+                in_flight -= bytes;
+                let dropped = i as u64 >= capacity
+                    || self
+                        .loss_interval
+                        .is_some_and(|n| packet_number % n == n - 1);
+                if dropped {
+                    lost = Some(sent_at);
+                    lost_bytes += bytes;
+                    continue;
+                }
+                now = sent_at + self.rtt;
+                rtt.update(Duration::ZERO, self.rtt);
+                controller.on_ack(now, sent_at, bytes, false, &rtt);
+                controller.on_end_acks(now, in_flight, false, Some(packet_number));
+            }
+            if let Some(sent_at) = lost {
+                now = now.max(sent_at + self.rtt);
+                controller.on_congestion_event(now, sent_at, false, lost_bytes);
+                controller.on_end_acks(now, 0, false, None);
+            }
+            windows.push(controller.window());
+        }
+        windows
+    }
+}
+
+fn mean(windows: &[u64]) -> u64 {
+    windows.iter().sum::<u64>() / windows.len() as u64
+}
+
+#[test]
+fn bbr_outperforms_new_reno_on_high_bdp_path() {
+    let scenario = Scenario {
+        bandwidth: 12_500_000,
+        rtt: Duration::from_millis(100),
+        buffer: 1_250_000,
+        loss_interval: None,
+        rounds: 200,
+    };
+    let now = Instant::now();
+    let bbr = scenario.run(Arc::new(BbrConfig::default()).build(now, MTU));
+    let new_reno = scenario.run(Arc::new(NewRenoConfig::default()).build(now, MTU));
+    let cubic = scenario.run(Arc::new(CubicConfig::default()).build(now, MTU));
+
+    let steady_state = scenario.rounds / 2..;
+    let bbr = mean(&bbr[steady_state.clone()]);
+    assert!(bbr > mean(&new_reno[steady_state.clone()]));
+    assert!(bbr > mean(&cubic[steady_state]));
+    // BBR should approach, but not exceed, what the path can hold
+    assert!(bbr > scenario.bdp());
+    assert!(bbr <= scenario.bdp() + scenario.buffer);
+}
+
+#[test]
+fn adaptive_factory_selects_controller() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let ecn_available = Arc::new(AtomicBool::new(true));
+    let condition = ecn_available.clone();
+    let config = Arc::new(AdaptiveControllerConfig::new(Arc::new(move || {
+        condition.load(Ordering::Relaxed)
+    })));
+
+    let controller = config.clone().build(Instant::now(), MTU);
+    assert!(controller.into_any().downcast::<Bbr>().is_ok());
+
+    ecn_available.store(false, Ordering::Relaxed);
+    let controller = config.build(Instant::now(), MTU);
+    assert!(controller.into_any().downcast::<Cubic>().is_ok());
+}
+
+#[test]
+fn controller_names() {
+    let now = Instant::now();
+    let name = |factory: Arc<dyn ControllerFactory>| factory.build(now, MTU).name();
+    assert_eq!(name(Arc::new(BbrConfig::default())), "bbr");
+    assert_eq!(name(Arc::new(CubicConfig::default())), "cubic");
+    assert_eq!(name(Arc::new(NewRenoConfig::default())), "new_reno");
+}
+
+#[test]
+fn bbr_bandwidth_filter_inspectable() {
+    let mut bbr = Bbr::new(Arc::new(BbrConfig::default()), MTU);
+    assert_eq!(bbr.bandwidth_filter().get(), 0);
+    let bytes = MTU as u64;
+    let rtt = RttEstimator::new(Duration::from_millis(20));
+    let start = Instant::now();
+    for packet_number in 0..20 {
+        let sent = start + Duration::from_millis(packet_number);
+        bbr.on_sent(sent, bytes, packet_number);
+    }
+    for packet_number in 0..20 {
+        let now = start + Duration::from_millis(20 + packet_number);
+        bbr.on_ack(now, now - Duration::from_millis(20), bytes, false, &rtt);
+        bbr.on_end_acks(now, 0, false, Some(packet_number));
+    }
+    assert_ne!(bbr.bandwidth_filter().get(), 0);
+    assert_eq!(
+        bbr.bandwidth_filter().get(),
+        bbr.bandwidth().as_bytes_per_sec()
+    );
+}
@@ -40,6 +40,10 @@ pub(super) struct PathData {
     /// Used in persistent congestion determination.
     pub(super) first_packet_after_rtt_sample: Option<(SpaceId, u64)>,
     pub(super) in_flight: InFlight,
+    /// Time at which the most recent packet counting towards `in_flight` was sent
+    ///
+    /// Used to tell the congestion controller how long the path was idle when sending resumes.
+    last_in_flight_sent: Option<Instant>,
     /// Number of the first packet sent on this path
     ///
     /// Used to determine whether a packet was sent on an earlier path. Insufficient to determine if
@@ -100,6 +104,7 @@ impl PathData {
                 ),
             first_packet_after_rtt_sample: None,
             in_flight: InFlight::new(),
+            last_in_flight_sent: None,
             first_packet: None,
             #[cfg(feature = "qlog")]
             recovery_metrics: RecoveryMetrics::default(),
@@ -113,7 +118,8 @@ impl PathData {
         generation: u64,
         now: Instant,
     ) -> Self {
-        let congestion = prev.congestion.clone_box();
+        let mut congestion = prev.congestion.clone_box();
+        congestion.on_path_change();
         let smoothed_rtt = prev.rtt.get();
         Self {
             remote,
@@ -129,6 +135,7 @@ impl PathData {
             mtud: prev.mtud.clone(),
             first_packet_after_rtt_sample: prev.first_packet_after_rtt_sample,
             in_flight: InFlight::new(),
+            last_in_flight_sent: None,
             first_packet: None,
             #[cfg(feature = "qlog")]
             recovery_metrics: prev.recovery_metrics.clone(),
@@ -161,6 +168,20 @@ impl PathData {
 
     /// Account for transmission of `packet` with number `pn` in `space`
     pub(super) fn sent(&mut self, pn: u64, packet: SentPacket, space: &mut PacketSpace) {
+        if packet.size != 0 {
+            // Nothing was in flight for longer than a round trip, so the path went idle rather
+            // than merely waiting for the previous acknowledgements
+            if let Some(last_sent) = self
+                .last_in_flight_sent
+                .filter(|_| self.in_flight.bytes == 0)
+            {
+                let idle_duration = packet.time_sent.saturating_duration_since(last_sent);
+                if idle_duration > self.rtt.get() {
+                    self.congestion.on_idle(idle_duration);
+                }
+            }
+            self.last_in_flight_sent = Some(packet.time_sent);
+        }
         self.in_flight.insert(&packet);
         if self.first_packet.is_none() {
             self.first_packet = Some(pn);
@@ -294,7 +315,7 @@ pub struct RttEstimator {
 }
 
 impl RttEstimator {
-    fn new(initial_rtt: Duration) -> Self {
+    pub(crate) fn new(initial_rtt: Duration) -> Self {
         Self {
             latest: initial_rtt,
             smoothed: None,
@@ -321,6 +342,11 @@ impl RttEstimator {
         self.min
     }
 
+    /// The most recent RTT measurement
+    pub(crate) fn latest(&self) -> Duration {
+        self.latest
+    }
+
     // PTO computed as described in RFC9002#6.2.1
     pub(crate) fn pto_base(&self) -> Duration {
         self.get() + cmp::max(4 * self.var, TIMER_GRANULARITY)
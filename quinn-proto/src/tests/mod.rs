@@ -521,6 +521,100 @@ fn congestion() {
     pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
 }
 
+#[test]
+fn congestion_controller_notified_of_idle() {
+    /// Wraps a controller, recording the idle periods it's notified of
+    #[derive(Clone)]
+    struct IdleRecorder {
+        inner: Arc<congestion::NewRenoConfig>,
+        idle: Arc<Mutex<Vec<Duration>>>,
+    }
+
+    impl congestion::ControllerFactory for IdleRecorder {
+        fn build(
+            self: Arc<Self>,
+            now: Instant,
+            current_mtu: u16,
+        ) -> Box<dyn congestion::Controller> {
+            Box::new(Recording {
+                inner: self.inner.clone().build(now, current_mtu),
+                idle: self.idle.clone(),
+            })
+        }
+    }
+
+    struct Recording {
+        inner: Box<dyn congestion::Controller>,
+        idle: Arc<Mutex<Vec<Duration>>>,
+    }
+
+    impl congestion::Controller for Recording {
+        fn on_congestion_event(
+            &mut self,
+            now: Instant,
+            sent: Instant,
+            is_persistent_congestion: bool,
+            lost_bytes: u64,
+        ) {
+            self.inner
+                .on_congestion_event(now, sent, is_persistent_congestion, lost_bytes);
+        }
+
+        fn on_idle(&mut self, idle_duration: Duration) {
+            self.idle.lock().unwrap().push(idle_duration);
+        }
+
+        fn on_mtu_update(&mut self, new_mtu: u16) {
+            self.inner.on_mtu_update(new_mtu);
+        }
+
+        fn window(&self) -> u64 {
+            self.inner.window()
+        }
+
+        fn clone_box(&self) -> Box<dyn congestion::Controller> {
+            Box::new(Self {
+                inner: self.inner.clone_box(),
+                idle: self.idle.clone(),
+            })
+        }
+
+        fn initial_window(&self) -> u64 {
+            self.inner.initial_window()
+        }
+
+        fn into_any(self: Box<Self>) -> Box<dyn std::any::Any> {
+            self
+        }
+    }
+
+    let _guard = subscribe();
+    let idle = Arc::new(Mutex::new(Vec::new()));
+    let mut transport = TransportConfig::default();
+    transport.congestion_controller_factory(Arc::new(IdleRecorder {
+        inner: Arc::new(congestion::NewRenoConfig::default()),
+        idle: idle.clone(),
+    }));
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect_with(client_config);
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+    pair.drive();
+    // Back-to-back sends don't count as idle
+    assert!(idle.lock().unwrap().is_empty());
+
+    pair.time += Duration::from_secs(5);
+    pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+    pair.drive();
+    let idle = idle.lock().unwrap();
+    assert_eq!(idle.len(), 1);
+    assert!(idle[0] >= Duration::from_secs(5));
+}
+
 #[test]
 fn high_latency_handshake() {
     let _guard = subscribe();